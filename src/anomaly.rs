@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::config::{Anomaly, Baseline};
+
+// The latencies that are normal for one hour of the day
+#[derive(Default, Serialize, Deserialize)]
+struct Hour {
+    count: usize,
+    // the ewma and its variance
+    mean: f64,
+    variance: f64,
+    // the latest samples for the median and the mad
+    recent: VecDeque<f64>,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    }
+    else {
+        values[middle]
+    }
+}
+
+impl Hour {
+    // A function that will tell what is normal and how far off a latency may be, None while too few samples came in
+    fn normal(&self, rule: &Anomaly) -> Option<(f64, f64)> {
+        if self.count < rule.min_samples {
+            return None;
+        }
+
+        match rule.baseline {
+            Baseline::Ewma => Some((self.mean, self.variance.sqrt())),
+            Baseline::Mad => {
+                let mut values: Vec<f64> = self.recent.iter().copied().collect();
+                let middle = median(&mut values);
+                let mut deviations: Vec<f64> = values.iter().map(|value| (value - middle).abs()).collect();
+                // 1.4826 turns the mad into a standard deviation for normally spread latencies
+                Some((middle, median(&mut deviations) * 1.4826))
+            }
+        }
+    }
+
+    fn learn(&mut self, latency: f64, rule: &Anomaly) {
+        if self.count == 0 {
+            self.mean = latency;
+        }
+        else {
+            let diff = latency - self.mean;
+            let step = rule.alpha * diff;
+            self.mean += step;
+            self.variance = (1.0 - rule.alpha) * (self.variance + diff * step);
+        }
+        self.count += 1;
+
+        self.recent.push_back(latency);
+        if self.recent.len() > rule.history {
+            self.recent.pop_front();
+        }
+    }
+}
+
+// What one latency says about a target
+pub struct Verdict {
+    // this latency is off the normal
+    pub abnormal: bool,
+    // set on the sample that makes enough abnormal ones in a row, the anomaly starts there
+    pub started: Option<String>,
+}
+
+// Learns the normal latency of a target for every hour of the day and tells when a run of latencies is off
+pub struct Detector {
+    rule: Anomaly,
+    hours: Vec<Hour>,
+    streak: u32,
+    pub active: bool,
+}
+
+impl Detector {
+    pub fn new(rule: Anomaly) -> Detector {
+        Detector {
+            rule,
+            hours: (0..24).map(|_| Hour::default()).collect(),
+            streak: 0,
+            active: false,
+        }
+    }
+
+    // A function that will start from the normal that was learned before the app was stopped, a new one when there is none or it can't be read
+    pub fn load(rule: Anomaly, path: &Path) -> Detector {
+        let mut detector = Detector::new(rule);
+        let saved = fs::read_to_string(path).ok().and_then(|data| serde_json::from_str::<Vec<Hour>>(&data).ok());
+        if let Some(hours) = saved.filter(|hours| hours.len() == 24) {
+            detector.hours = hours;
+            let history = detector.rule.history;
+            for hour in detector.hours.iter_mut() {
+                while hour.recent.len() > history {
+                    hour.recent.pop_front();
+                }
+            }
+        }
+        detector
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&self.hours)?)
+    }
+
+    // A function that will judge a latency against the normal of its hour and then learn it
+    pub fn check(&mut self, hour: usize, latency: i32) -> Verdict {
+        let latency = latency as f64;
+        let baseline = &mut self.hours[hour % 24];
+
+        // A spread of almost nothing would make every millisecond an anomaly
+        let normal = baseline.normal(&self.rule).map(|(normal, spread)| (normal, spread.max(1.0)));
+        let score = normal.map(|(normal, spread)| ((latency - normal) / spread, normal));
+
+        // An outlier is learned as if it was only just off, so a real change of the normal is picked up slowly and one spike doesn't hide the next ones
+        let learned = match normal {
+            Some((normal, spread)) => {
+                let limit = self.rule.threshold * spread;
+                latency.clamp(normal - limit, normal + limit)
+            }
+            None => latency,
+        };
+        baseline.learn(learned, &self.rule);
+
+        let abnormal = score.is_some_and(|(score, _)| score.abs() > self.rule.threshold);
+        let mut verdict = Verdict { abnormal, started: None };
+
+        if abnormal {
+            self.streak += 1;
+            if self.streak >= self.rule.consecutive && !self.active {
+                self.active = true;
+                let (score, normal) = score.unwrap();
+                let way = if score > 0.0 { "slower" } else { "faster" };
+                verdict.started = Some(format!("{} ms is {:.1} deviations {} than the normal {:.0} ms for {:02}:00",
+                    latency, score.abs(), way, normal, hour % 24));
+            }
+        }
+        else {
+            self.streak = 0;
+            self.active = false;
+        }
+
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A detector that has seen a steady 100 ms at 09:00
+    fn steady(baseline: Baseline) -> Detector {
+        let rule = Anomaly {
+            baseline,
+            min_samples: 5,
+            ..Anomaly::default()
+        };
+        let mut detector = Detector::new(rule);
+        for _ in 0..10 {
+            assert!(!detector.check(9, 100).abnormal);
+        }
+        detector
+    }
+
+    #[test]
+    fn a_spike_is_learned_as_only_just_off() {
+        let mut detector = steady(Baseline::Ewma);
+        assert!(detector.check(9, 10_000).abnormal);
+        // the spread of a steady target is taken as 1 ms so the spike counts as 103 ms
+        let hour = &detector.hours[9];
+        assert_eq!(hour.recent.back(), Some(&103.0));
+        assert!((hour.mean - 100.3).abs() < 1e-9, "{}", hour.mean);
+
+        let mut detector = steady(Baseline::Mad);
+        assert!(detector.check(9, 10_000).abnormal);
+        assert_eq!(detector.hours[9].recent.back(), Some(&103.0));
+    }
+
+    #[test]
+    fn an_anomaly_starts_after_enough_abnormal_samples_in_a_row() {
+        let mut detector = steady(Baseline::Ewma);
+        assert!(detector.check(9, 10_000).started.is_none());
+        assert!(detector.check(9, 10_000).started.is_none());
+        let verdict = detector.check(9, 10_000);
+        assert!(verdict.started.unwrap().contains("slower than the normal"));
+        assert!(detector.active);
+        // it is only reported once while it lasts
+        let verdict = detector.check(9, 10_000);
+        assert!(verdict.abnormal && verdict.started.is_none());
+
+        // one normal sample ends it and the count starts over
+        assert!(!detector.check(9, 100).abnormal);
+        assert!(!detector.active);
+        assert!(detector.check(9, 10_000).started.is_none());
+        assert!(detector.check(9, 10_000).started.is_none());
+        assert!(detector.check(9, 10_000).started.is_some());
+    }
+
+    #[test]
+    fn every_hour_learns_its_own_normal() {
+        let mut detector = steady(Baseline::Ewma);
+        // nothing was learned for 03:00 yet so nothing there is judged
+        assert!(!detector.check(3, 10_000).abnormal);
+        assert!(detector.check(33, 10_000).abnormal);
+    }
+}
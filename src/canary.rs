@@ -0,0 +1,53 @@
+use isahc::config::Configurable;
+use isahc::HttpClient;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+// Endpoints that should always answer, when none of them does it is our own network that is down and not the targets
+pub struct Canaries {
+    urls: Vec<String>,
+    client: HttpClient,
+    up: AtomicBool,
+}
+
+impl Canaries {
+    pub fn new(urls: Vec<String>) -> Canaries {
+        let client = HttpClient::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        Canaries { urls, client, up: AtomicBool::new(true) }
+    }
+
+    // What the last check said, without any canaries the network is always up
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Relaxed)
+    }
+
+    // A function that will check the canaries one by one, the network is up as soon as one of them answers
+    pub async fn check(&self) -> bool {
+        let mut up = self.urls.is_empty();
+        for url in &self.urls {
+            if let Ok(response) = self.client.get_async(url.as_str()).await {
+                if response.status().is_success() || response.status().is_redirection() {
+                    up = true;
+                    break;
+                }
+            }
+        }
+        self.up.store(up, Ordering::Relaxed);
+        up
+    }
+}
+
+// A function that will keep on checking the canaries so the TUI knows when the network is down, it never ends
+pub async fn watch(canaries: Arc<Canaries>, every: Duration) {
+    let mut ticker = tokio::time::interval(every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        canaries.check().await;
+    }
+}
@@ -0,0 +1,134 @@
+use isahc::http::Uri;
+use openssl::asn1::Asn1Time;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::{X509NameRef, X509Ref};
+use serde::{Deserialize, Serialize};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// What we know about the certificate of a target the last time it was checked
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CertInfo {
+    pub target: String,
+    pub subject: String,
+    pub issuer: String,
+    // subject alternative names joined with a space
+    pub sans: String,
+    // of the certificate in the chain that runs out first, that is the one that breaks the site
+    pub not_after: String,
+    pub days_left: i32,
+    // the subject of that certificate, the leaf itself most of the time
+    pub expires_first: String,
+    // how many certificates the server sent, the leaf included
+    pub chain_length: usize,
+}
+
+// A function that will turn a certificate name into "CN=example.com, O=Example"
+fn format_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = String::from_utf8_lossy(entry.data().as_slice());
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn days_left(cert: &X509Ref) -> Result<i32, String> {
+    let now = Asn1Time::days_from_now(0).map_err(|err| err.to_string())?;
+    let diff = now.diff(cert.not_after()).map_err(|err| err.to_string())?;
+    Ok(diff.days)
+}
+
+// A function that will connect to an https target and read its certificate chain
+pub fn check_cert(name: &str, url: &str, timeout: Duration) -> Result<CertInfo, String> {
+    let uri: Uri = url.parse().map_err(|_| format!("invalid url {}", url))?;
+    if uri.scheme_str() != Some("https") {
+        return Err(format!("{} is not https", url));
+    }
+    let host = uri.host().ok_or(format!("no host in {}", url))?;
+    let port = uri.port_u16().unwrap_or(443);
+
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or(format!("no address found for {}", host))?;
+    let stream = TcpStream::connect_timeout(&address, timeout).map_err(|err| err.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+
+    // Don't verify here, an expired or self signed certificate is exactly what we want to see
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|err| err.to_string())?;
+    builder.set_verify(SslVerifyMode::NONE);
+    let connector = builder.build();
+    let tls = connector.connect(host, stream).map_err(|err| err.to_string())?;
+
+    let ssl = tls.ssl();
+    let cert = ssl.peer_certificate().ok_or(format!("{} sent no certificate", host))?;
+
+    let sans = match cert.subject_alt_names() {
+        Some(names) => names
+            .iter()
+            .filter_map(|name| name.dnsname().map(|dns| dns.to_string()))
+            .collect::<Vec<String>>()
+            .join(" "),
+        None => String::new(),
+    };
+
+    // An intermediate that runs out before the leaf breaks the site just the same
+    let mut first: &X509Ref = &cert;
+    let mut chain_length = 1;
+    if let Some(chain) = ssl.peer_cert_chain() {
+        chain_length = chain.len().max(1);
+        for link in chain {
+            if link.not_after() < first.not_after() {
+                first = link;
+            }
+        }
+    }
+
+    Ok(CertInfo {
+        target: name.to_string(),
+        subject: format_name(cert.subject_name()),
+        issuer: format_name(cert.issuer_name()),
+        sans,
+        not_after: first.not_after().to_string(),
+        days_left: days_left(first)?,
+        expires_first: format_name(first.subject_name()),
+        chain_length,
+    })
+}
+
+// Remembers which expiry warnings were already sent so each threshold is only sent once
+pub struct CertWatch {
+    warn_days: Vec<i32>,
+    warned: Vec<i32>,
+}
+
+impl CertWatch {
+    pub fn new(mut warn_days: Vec<i32>) -> CertWatch {
+        warn_days.sort();
+        CertWatch {
+            warn_days,
+            warned: Vec::new(),
+        }
+    }
+
+    // Gives back the threshold to warn about when the certificate has just crossed one
+    pub fn check(&mut self, days_left: i32) -> Option<i32> {
+        // A renewed certificate starts over
+        if self.warn_days.iter().all(|&days| days_left > days) {
+            self.warned.clear();
+            return None;
+        }
+
+        let threshold = *self.warn_days.iter().find(|&&days| days_left <= days)?;
+        if self.warned.contains(&threshold) {
+            return None;
+        }
+        self.warned.push(threshold);
+        Some(threshold)
+    }
+}
@@ -0,0 +1,1221 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use isahc::http::{Method, Uri};
+use regex::Regex;
+use scraper::Selector;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use crate::jsonpath::{parse_assertion, parse_path, Assertion, Step as PathStep};
+use crate::window::{check_period, check_window, describe_period, is_over};
+
+// The file that the app will read its monitored targets from
+pub const CONFIG_PATH: &str = "config.json";
+
+// How often a target is probed when nothing else is said (the old five-minute marks)
+pub const DEFAULT_INTERVAL: &str = "5m";
+
+// How long a probe may take before it counts as a timeout
+pub const DEFAULT_TIMEOUT: &str = "10s";
+
+// The endpoints that tell whether our own network is up, the one that was always checked at the start
+pub const DEFAULT_CANARY: &str = "https://www.google.com";
+
+// How often the canaries are checked while the targets are checked
+pub const DEFAULT_CANARY_EVERY: &str = "30s";
+
+// Where the app listens for the pings of heartbeat jobs
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8787";
+
+// How far back the statistics in the TUI and in the alerts go when nothing else is said
+pub const DEFAULT_STATS_RANGE: &str = "24h";
+
+// The ranges that 's' goes through while checking, the samples of the longest one are kept
+pub const STATS_RANGES: [&str; 3] = ["1h", "24h", "7d"];
+
+// The longest a throttled target is left alone, a Retry-After far in the future is cut down to this
+pub const MAX_THROTTLE_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Where SendGrid takes the emails
+pub const DEFAULT_SENDGRID_URL: &str = "https://api.sendgrid.com/v3/mail/send";
+
+// What part of a page is watched for changes, the whole body when nothing is set
+#[derive(Deserialize, Clone, Default)]
+pub struct Content {
+    // a CSS selector like "main" or "#price"
+    pub selector: Option<String>,
+    // a regex that picks the text out, the first group when it has one
+    pub regex: Option<String>,
+    // the selector and the regex as they are read when the config is loaded
+    #[serde(skip)]
+    pub selector_compiled: Option<Selector>,
+    #[serde(skip)]
+    pub regex_compiled: Option<Regex>,
+}
+
+impl Content {
+    pub fn compile(&mut self) -> Result<(), String> {
+        if let Some(selector) = &self.selector {
+            self.selector_compiled = Some(Selector::parse(selector).map_err(|_| format!("invalid content selector \"{}\"", selector))?);
+        }
+        if let Some(pattern) = &self.regex {
+            self.regex_compiled = Some(Regex::new(pattern).map_err(|err| format!("invalid content regex, {}", err))?);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    #[default]
+    Once,
+    Daily,
+    Weekly,
+}
+
+// A stretch of time from a start to an end or for a duration, it can open again every day or week
+#[derive(Deserialize, Clone, Default)]
+pub struct Period {
+    // "YYYY-MM-DD HH:MM" or only "HH:MM"
+    pub start: Option<String>,
+    pub end: Option<String>,
+    // how long the period lasts, instead of an end
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub repeat: Repeat,
+    // the days of a weekly period e.g. ["mon", "thu"]
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Schedule {
+    // when to stop checking, left empty the target is checked until the app is stopped
+    #[serde(default)]
+    pub hour: String,
+    #[serde(default)]
+    pub minute: String,
+    // the window that the target is checked in
+    #[serde(flatten)]
+    pub window: Period,
+    #[serde(default = "default_interval")]
+    pub interval: String,
+    #[serde(default)]
+    pub jitter: String,
+}
+
+fn default_interval() -> String {
+    DEFAULT_INTERVAL.to_string()
+}
+
+fn default_timeout() -> String {
+    DEFAULT_TIMEOUT.to_string()
+}
+
+impl Schedule {
+    pub fn has_end(&self) -> bool {
+        (!self.hour.is_empty() && !self.minute.is_empty()) || self.window.end.is_some() || self.window.duration.is_some()
+    }
+
+    // The window of the schedule, an end hour and minute picked in the TUI becomes its end
+    pub fn period(&self) -> Period {
+        let mut period = self.window.clone();
+        if period.end.is_none() && period.duration.is_none() && !self.hour.is_empty() {
+            period.end = Some(clock_time(&self.hour, &self.minute).format("%H:%M").to_string());
+        }
+        period
+    }
+
+    pub fn interval(&self) -> Duration {
+        parse_duration(&self.interval).unwrap()
+    }
+
+    pub fn jitter(&self) -> Duration {
+        if self.jitter.is_empty() {
+            return Duration::ZERO;
+        }
+        parse_duration(&self.jitter).unwrap()
+    }
+}
+
+// A function that will turn "10s", "1m", "15m" or "2h" into a duration
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        _ => return None,
+    };
+
+    if seconds == 0 {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+// How a target decides that it is down and when it is flapping
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Policy {
+    // consecutive failed probes before the target is DOWN
+    pub failures: u32,
+    // extra attempts inside one probe before it counts as a failure
+    pub retries: u32,
+    // wait before the first retry, doubled on every retry after that
+    pub backoff: String,
+    // state changes inside flap_window that make the target FLAPPING
+    pub flap_changes: usize,
+    pub flap_window: String,
+    // a target that rate limits us counts as failing instead of THROTTLED
+    pub throttled_is_down: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            failures: 3,
+            retries: 1,
+            backoff: "2s".to_string(),
+            flap_changes: 4,
+            flap_window: "15m".to_string(),
+            throttled_is_down: false,
+        }
+    }
+}
+
+impl Policy {
+    pub fn backoff(&self) -> Duration {
+        parse_duration(&self.backoff).unwrap()
+    }
+
+    pub fn flap_window(&self) -> Duration {
+        parse_duration(&self.flap_window).unwrap()
+    }
+}
+
+// Response times in milliseconds that make a target DEGRADED (warning) or count as a failure (critical)
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Latency {
+    pub warning_ms: Option<i32>,
+    pub critical_ms: Option<i32>,
+}
+
+// What a response has to look like for the probe to count as a success
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Expect {
+    // accepted status codes, each one is a code "204", a range "200-299" or a class "2xx"
+    pub status: Vec<String>,
+    pub follow_redirects: bool,
+    // more hops than this is a failure
+    pub max_redirects: usize,
+    // a regex that the url which answered in the end has to match
+    pub final_url: Option<String>,
+    pub body_contains: Option<String>,
+    pub body_regex: Option<String>,
+    // turn the body rules around, the text or pattern must NOT be in the body
+    pub body_absent: bool,
+    // assertions on a JSON body e.g. `$.db == "up"` or `$.queue_depth < 1000`
+    pub json: Vec<String>,
+    // the patterns and the json assertions as they are read when the config is loaded
+    #[serde(skip)]
+    pub final_url_regex: Option<Regex>,
+    #[serde(skip)]
+    pub body_regex_compiled: Option<Regex>,
+    #[serde(skip)]
+    pub assertions: Vec<Assertion>,
+}
+
+impl Default for Expect {
+    fn default() -> Expect {
+        Expect {
+            status: vec!["200-299".to_string()],
+            follow_redirects: true,
+            max_redirects: 10,
+            final_url: None,
+            body_contains: None,
+            body_regex: None,
+            body_absent: false,
+            json: Vec::new(),
+            final_url_regex: None,
+            body_regex_compiled: None,
+            assertions: Vec::new(),
+        }
+    }
+}
+
+impl Expect {
+    pub fn accepts_status(&self, code: u16) -> bool {
+        self.status.iter().any(|spec| status_matches(spec, code) == Some(true))
+    }
+
+    // A function that will read the rules once so the probes don't have to
+    pub fn compile(&mut self) -> Result<(), String> {
+        if let Some(pattern) = &self.final_url {
+            self.final_url_regex = Some(Regex::new(pattern).map_err(|err| format!("invalid final_url, {}", err))?);
+        }
+        if let Some(pattern) = &self.body_regex {
+            self.body_regex_compiled = Some(Regex::new(pattern).map_err(|err| format!("invalid body_regex, {}", err))?);
+        }
+        self.assertions = self.json.iter().map(|text| parse_assertion(text)).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
+
+// A function that will check a status code against "204", "200-299" or "2xx", None when the rule is not valid
+pub fn status_matches(spec: &str, code: u16) -> Option<bool> {
+    let spec = spec.trim();
+
+    if let Some((low, high)) = spec.split_once('-') {
+        let low: u16 = low.trim().parse().ok()?;
+        let high: u16 = high.trim().parse().ok()?;
+        return Some(low <= code && code <= high);
+    }
+
+    if spec.len() == 3 && spec.ends_with("xx") {
+        let class: u16 = spec[..1].parse().ok()?;
+        return Some(code / 100 == class);
+    }
+
+    let exact: u16 = spec.parse().ok()?;
+    Some(code == exact)
+}
+
+// When to warn about the certificate of an https target running out
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CertPolicy {
+    pub warn_days: Vec<i32>,
+    // certificates don't change often so they are not read on every probe
+    pub check_every: String,
+}
+
+impl Default for CertPolicy {
+    fn default() -> CertPolicy {
+        CertPolicy {
+            warn_days: vec![30, 7],
+            check_every: "1h".to_string(),
+        }
+    }
+}
+
+impl CertPolicy {
+    pub fn check_every(&self) -> Duration {
+        parse_duration(&self.check_every).unwrap()
+    }
+}
+
+// How the normal latency of a target is learned, per hour of the day
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Baseline {
+    // an average that follows the latest samples more than the old ones
+    #[default]
+    Ewma,
+    // the median and the median absolute deviation of the latest samples, a few outliers don't move it
+    Mad,
+}
+
+// When a latency is far enough off the normal for that hour to be an anomaly
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Anomaly {
+    pub baseline: Baseline,
+    // how many deviations a latency may be off the normal
+    pub threshold: f64,
+    // how many samples an hour needs before its latencies are judged
+    pub min_samples: usize,
+    // how many abnormal samples in a row are an anomaly, one odd sample is not
+    pub consecutive: u32,
+    // how much weight the latest sample gets in the ewma
+    pub alpha: f64,
+    // how many samples per hour the mad baseline keeps
+    pub history: usize,
+}
+
+impl Default for Anomaly {
+    fn default() -> Anomaly {
+        Anomaly {
+            baseline: Baseline::Ewma,
+            threshold: 3.0,
+            min_samples: 20,
+            consecutive: 3,
+            alpha: 0.1,
+            history: 200,
+        }
+    }
+}
+
+// How to log in to a target, secrets should be written as "${ENV_VAR}"
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+// The http request a probe sends, the default is a plain GET
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RequestSpec {
+    pub method: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    // a json body, also sets the Content-Type header
+    pub json: Option<serde_json::Value>,
+    pub auth: Option<Auth>,
+}
+
+impl Default for RequestSpec {
+    fn default() -> RequestSpec {
+        RequestSpec {
+            method: "GET".to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+            json: None,
+            auth: None,
+        }
+    }
+}
+
+impl RequestSpec {
+    // A function that will check that every secret the request names is set, a missing one stops the config instead of failing every probe
+    pub fn check_env(&self) -> Result<(), String> {
+        let mut texts: Vec<&str> = self.headers.values().map(String::as_str).collect();
+        texts.extend(self.body.as_deref());
+        match &self.auth {
+            Some(Auth::Basic { username, password }) => texts.extend([username.as_str(), password.as_str()]),
+            Some(Auth::Bearer { token }) => texts.push(token),
+            None => {}
+        }
+        if let Some(json) = &self.json {
+            json_strings(json, &mut texts);
+        }
+        for text in texts {
+            expand_env(text)?;
+        }
+        Ok(())
+    }
+}
+
+// A function that will collect the strings and keys of a json body, the places where secrets get filled in
+fn json_strings<'a>(value: &'a serde_json::Value, texts: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::String(text) => texts.push(text),
+        serde_json::Value::Array(items) => items.iter().for_each(|item| json_strings(item, texts)),
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields {
+                texts.push(name);
+                json_strings(field, texts);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A function that will replace every "${NAME}" in a text with the environment variable NAME
+pub fn expand_env(text: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("missing }} in \"{}\"", text)),
+        };
+        let name = &rest[start + 2..end];
+        let value = env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+// One request of a multi-step check, what it takes out of its response is used by the later steps as "{{name}}"
+#[derive(Deserialize, Clone)]
+pub struct Step {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub request: RequestSpec,
+    #[serde(default)]
+    pub expect: Expect,
+    // name -> where the value comes from, "$.path" in a JSON body, "cookie:NAME", "header:NAME" or "regex:PATTERN"
+    #[serde(default)]
+    pub extract: BTreeMap<String, String>,
+    // the extract rules as they are read when the config is loaded
+    #[serde(skip)]
+    pub extracts: BTreeMap<String, Extract>,
+}
+
+// Where a step takes a value from
+#[derive(Clone)]
+pub enum Extract {
+    Json(Vec<PathStep>),
+    Cookie(String),
+    Header(String),
+    Regex(Regex),
+}
+
+// A function that will replace every "{{name}}" in a text with a value an earlier step took out
+pub fn expand_vars(text: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => return Err(format!("missing }}}} in \"{}\"", text)),
+        };
+        let name = rest[start + 2..end].trim();
+        let value = vars.get(name).ok_or(format!("no earlier step gives {{{{{}}}}}", name))?;
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(value);
+        rest = &rest[end + 2..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+// A function that will read where a step takes a value from
+pub fn parse_extract(source: &str) -> Result<Extract, String> {
+    if source.starts_with('$') {
+        Ok(Extract::Json(parse_path(source)?))
+    }
+    else if let Some(pattern) = source.strip_prefix("regex:") {
+        let regex = Regex::new(pattern).map_err(|err| format!("invalid extract regex, {}", err))?;
+        if regex.captures_len() < 2 {
+            return Err(format!("extract regex /{}/ needs a group", pattern));
+        }
+        Ok(Extract::Regex(regex))
+    }
+    else if let Some(name) = source.strip_prefix("cookie:") {
+        Ok(Extract::Cookie(name.to_string()))
+    }
+    else if let Some(name) = source.strip_prefix("header:") {
+        Ok(Extract::Header(name.to_string()))
+    }
+    else {
+        Err(format!("invalid extract \"{}\"", source))
+    }
+}
+
+// What kind of probe a target gets, a tcp target's url is just "host:port"
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Http,
+    Tcp,
+    // not probed, a job pings the app instead
+    Heartbeat,
+}
+
+// How often a heartbeat job should ping and how late it may be before the target is DOWN
+#[derive(Deserialize, Clone)]
+pub struct Heartbeat {
+    pub period: String,
+    #[serde(default = "default_grace")]
+    pub grace: String,
+    // the job calls /ping/<token>, the name of the target when it is not set
+    #[serde(default)]
+    pub token: String,
+}
+
+fn default_grace() -> String {
+    "5m".to_string()
+}
+
+impl Heartbeat {
+    // How long after a ping the next one may come
+    pub fn allowed(&self) -> Duration {
+        parse_duration(&self.period).unwrap() + parse_duration(&self.grace).unwrap()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Target {
+    pub name: String,
+    #[serde(default)]
+    pub kind: Kind,
+    // a heartbeat target gets the url that its job has to call
+    #[serde(default)]
+    pub url: String,
+    #[serde(default = "default_timeout")]
+    pub timeout: String,
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub expect: Expect,
+    #[serde(default)]
+    pub latency: Latency,
+    // learn the normal latency and tell when it is off, left out nothing is learned
+    pub anomaly: Option<Anomaly>,
+    #[serde(default)]
+    pub cert: CertPolicy,
+    #[serde(default)]
+    pub request: RequestSpec,
+    // planned downtime, the target is still probed but nobody is notified
+    #[serde(default)]
+    pub maintenance: Vec<Period>,
+    // watch the page for changes, left out the content is not watched
+    pub content: Option<Content>,
+    pub heartbeat: Option<Heartbeat>,
+    // the names of the targets this one sits behind, while one of them is DOWN this target doesn't alert
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    // a flow of requests that share cookies and values, the url is the first step's when it is not set
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    // the notifiers that hear about this target, every notifier when it is empty
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+// Where a notifier sends the alerts, the SendGrid one reads its key and addresses from .env
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    Sendgrid {
+        #[serde(default = "default_sendgrid_url")]
+        url: String,
+    },
+    // posts every event as JSON
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    // a Slack incoming webhook
+    Slack { url: String },
+}
+
+impl NotifierConfig {
+    pub fn url(&self) -> &str {
+        match self {
+            NotifierConfig::Sendgrid { url } => url,
+            NotifierConfig::Webhook { url, .. } => url,
+            NotifierConfig::Slack { url } => url,
+        }
+    }
+
+    // A function that will fill in the secrets of the url and the headers from the environment
+    pub fn expanded(&self) -> Result<NotifierConfig, String> {
+        Ok(match self {
+            NotifierConfig::Sendgrid { url } => NotifierConfig::Sendgrid { url: expand_env(url)? },
+            NotifierConfig::Webhook { url, headers } => NotifierConfig::Webhook {
+                url: expand_env(url)?,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), expand_env(value)?)))
+                    .collect::<Result<_, String>>()?,
+            },
+            NotifierConfig::Slack { url } => NotifierConfig::Slack { url: expand_env(url)? },
+        })
+    }
+}
+
+fn default_sendgrid_url() -> String {
+    DEFAULT_SENDGRID_URL.to_string()
+}
+
+// The email that the app always sent when nothing else is set
+fn default_notifiers() -> BTreeMap<String, NotifierConfig> {
+    let mut notifiers = BTreeMap::new();
+    notifiers.insert("email".to_string(), NotifierConfig::Sendgrid { url: default_sendgrid_url() });
+    notifiers
+}
+
+// When the daemon sends its report email, either "every" some duration or daily at "HH:MM"
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ReportSchedule {
+    pub every: Option<String>,
+    pub daily_at: Option<String>,
+    // the zone that the report email shows its times in, the zone of the app when it is not set
+    pub timezone: Option<String>,
+    // the notifiers that get the report, every notifier when it is empty
+    pub notify: Vec<String>,
+}
+
+impl Default for ReportSchedule {
+    fn default() -> ReportSchedule {
+        ReportSchedule {
+            every: None,
+            daily_at: Some("09:00".to_string()),
+            timezone: None,
+            notify: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    // keep on checking until the app is stopped and send the report on a schedule
+    #[serde(default)]
+    pub daemon: bool,
+    #[serde(default)]
+    pub report: ReportSchedule,
+    // an IANA name like "Asia/Bangkok" that end times are read in and times are shown in, the system zone when it is not set
+    pub timezone: Option<String>,
+    // the address of the listener for heartbeat pings
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    // reliable endpoints, when none of them answers a failed target is unknown instead of DOWN
+    #[serde(default = "default_canaries")]
+    pub canaries: Vec<String>,
+    #[serde(default = "default_canary_every")]
+    pub canary_every: String,
+    // how far back the statistics under each target and in the alerts go
+    #[serde(default = "default_stats_range")]
+    pub stats_range: String,
+    // name -> where alerts and reports go
+    #[serde(default = "default_notifiers")]
+    pub notifiers: BTreeMap<String, NotifierConfig>,
+    // what was left out of the file while loading it, shown in the Time panel
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+fn default_canaries() -> Vec<String> {
+    vec![DEFAULT_CANARY.to_string()]
+}
+
+fn default_canary_every() -> String {
+    DEFAULT_CANARY_EVERY.to_string()
+}
+
+fn default_listen() -> String {
+    DEFAULT_LISTEN.to_string()
+}
+
+fn default_stats_range() -> String {
+    DEFAULT_STATS_RANGE.to_string()
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            targets: Vec::new(),
+            daemon: false,
+            report: ReportSchedule::default(),
+            timezone: None,
+            listen: default_listen(),
+            canaries: default_canaries(),
+            canary_every: default_canary_every(),
+            stats_range: default_stats_range(),
+            notifiers: default_notifiers(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+// Everything about a run that is not a target
+#[derive(Clone)]
+pub struct Settings {
+    pub daemon: bool,
+    pub report: ReportSchedule,
+    // the zone that end times are picked in and the status is shown in
+    pub zone: Zone,
+    pub report_zone: Zone,
+    pub listen: String,
+    pub canaries: Vec<String>,
+    pub canary_every: String,
+    pub stats_range: String,
+    pub notifiers: BTreeMap<String, NotifierConfig>,
+    pub warnings: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            daemon: false,
+            report: ReportSchedule::default(),
+            zone: Zone::Local,
+            report_zone: Zone::Local,
+            listen: default_listen(),
+            canaries: default_canaries(),
+            canary_every: default_canary_every(),
+            stats_range: default_stats_range(),
+            notifiers: default_notifiers(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn canary_every(&self) -> Duration {
+        parse_duration(&self.canary_every).unwrap()
+    }
+
+    pub fn stats_range(&self) -> Duration {
+        parse_duration(&self.stats_range).unwrap()
+    }
+}
+
+impl Config {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            daemon: self.daemon,
+            report: self.report.clone(),
+            zone: self.zone(),
+            report_zone: self.report_zone(),
+            listen: self.listen.clone(),
+            canaries: self.canaries.clone(),
+            canary_every: self.canary_every.clone(),
+            stats_range: self.stats_range.clone(),
+            notifiers: self.notifiers.clone(),
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    pub fn zone(&self) -> Zone {
+        Zone::from_name(self.timezone.as_deref()).unwrap()
+    }
+
+    pub fn report_zone(&self) -> Zone {
+        match &self.report.timezone {
+            Some(name) => Zone::from_name(Some(name)).unwrap(),
+            None => self.zone(),
+        }
+    }
+}
+
+// The time zone that a time is read in or shown in, samples are always saved in UTC
+#[derive(Clone, Copy)]
+pub enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    // No name is the zone of the system
+    pub fn from_name(name: Option<&str>) -> Result<Zone, String> {
+        match name {
+            None => Ok(Zone::Local),
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Ok(Zone::Named(tz)),
+                Err(_) => Err(format!("unknown time zone \"{}\"", name)),
+            },
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Zone::Local => "local".to_string(),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+
+    // A function that will show a UTC time in this zone
+    pub fn show(&self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            Zone::Local => time.with_timezone(&Local).format(format).to_string(),
+            Zone::Named(tz) => time.with_timezone(tz).format(format).to_string(),
+        }
+    }
+
+    // A function that will show a time saved in a sample in this zone, a time that can't be read is shown as it is
+    pub fn show_saved(&self, saved: &str, format: &str) -> String {
+        match DateTime::parse_from_rfc3339(saved) {
+            Ok(time) => self.show(time.with_timezone(&Utc), format),
+            Err(_) => saved.to_string(),
+        }
+    }
+
+    // A function that will turn a date and time on the clocks of this zone into UTC, a time that the clocks skip gives None
+    pub fn at(&self, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+        let local = date.and_time(time);
+        match self {
+            Zone::Local => Local.from_local_datetime(&local).earliest().map(|time| time.with_timezone(&Utc)),
+            Zone::Named(tz) => tz.from_local_datetime(&local).earliest().map(|time| time.with_timezone(&Utc)),
+        }
+    }
+
+    // The date that the clocks of this zone show at a moment
+    pub fn date_of(&self, time: DateTime<Utc>) -> NaiveDate {
+        match self {
+            Zone::Local => time.with_timezone(&Local).date_naive(),
+            Zone::Named(tz) => time.with_timezone(tz).date_naive(),
+        }
+    }
+
+    // The hour that the clocks of this zone show at a moment
+    pub fn hour_of(&self, time: DateTime<Utc>) -> usize {
+        match self {
+            Zone::Local => time.with_timezone(&Local).hour() as usize,
+            Zone::Named(tz) => time.with_timezone(tz).hour() as usize,
+        }
+    }
+
+    // A function that will find the first moment after `after` that the clocks show `time`, a day that skips it is passed over
+    pub fn next_at(&self, after: DateTime<Utc>, time: NaiveTime) -> DateTime<Utc> {
+        let mut date = self.date_of(after);
+        loop {
+            if let Some(next) = self.at(date, time) {
+                if next > after {
+                    return next;
+                }
+            }
+            date = date.succ_opt().unwrap();
+        }
+    }
+
+    // A function that will tell how long it is until the next HH:MM in this zone
+    pub fn time_until(&self, hour: &str, minute: &str) -> Duration {
+        let now = Utc::now();
+        (self.next_at(now, clock_time(hour, minute)) - now).to_std().unwrap_or_default()
+    }
+}
+
+// A function that will turn an hour and minute picked in the TUI into a time of the day
+pub fn clock_time(hour: &str, minute: &str) -> NaiveTime {
+    let hour: u32 = hour.parse::<u32>().unwrap_or(0) % 24;
+    let minute: u32 = minute.parse::<u32>().unwrap_or(0) % 60;
+    NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+}
+
+impl Target {
+    // A function that will read the rules of the target and its steps once, a target has to be compiled before it is probed
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.expect.compile()?;
+        if let Some(content) = self.content.as_mut() {
+            content.compile()?;
+        }
+        for step in self.steps.iter_mut() {
+            step.expect.compile().map_err(|err| format!("step {}: {}", step.name, err))?;
+            for (name, source) in &step.extract {
+                let rule = parse_extract(source).map_err(|err| format!("step {}: {}", step.name, err))?;
+                step.extracts.insert(name.clone(), rule);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        parse_duration(&self.timeout).unwrap()
+    }
+
+    /* Build a target from what the user typed in the Website box,
+    "name=url" gives the target a name otherwise the url is used as the name,
+    an interval can follow after a space e.g. "name=url 1m",
+    a url like "tcp://host:port" makes it a tcp port check */
+    pub fn from_input(input: &str, hour: &str, minute: &str) -> Target {
+        let (input, interval) = match input.trim().rsplit_once(' ') {
+            Some((rest, interval)) if parse_duration(interval).is_some() => (rest, interval),
+            _ => (input, DEFAULT_INTERVAL),
+        };
+
+        let (name, url) = match input.split_once('=') {
+            Some((name, url)) => (name.trim(), url.trim()),
+            None => (input.trim(), input.trim()),
+        };
+
+        let (kind, url) = match url.strip_prefix("tcp://") {
+            Some(address) => (Kind::Tcp, address),
+            None => (Kind::Http, url),
+        };
+
+        Target {
+            name: name.to_string(),
+            kind,
+            url: url.to_string(),
+            timeout: default_timeout(),
+            schedule: Schedule {
+                hour: hour.to_string(),
+                minute: minute.to_string(),
+                window: Period::default(),
+                interval: interval.to_string(),
+                jitter: String::new(),
+            },
+            policy: Policy::default(),
+            expect: Expect::default(),
+            latency: Latency::default(),
+            anomaly: None,
+            cert: CertPolicy::default(),
+            request: RequestSpec::default(),
+            maintenance: Vec::new(),
+            content: None,
+            heartbeat: None,
+            depends_on: Vec::new(),
+            steps: Vec::new(),
+            notify: Vec::new(),
+        }
+    }
+}
+
+// A function that will check that every parent is a target and that no target ends up depending on itself
+fn check_dependencies(targets: &[Target]) -> Result<(), String> {
+    for target in targets {
+        for parent in &target.depends_on {
+            if !targets.iter().any(|other| other.name == *parent) {
+                return Err(format!("{}: depends on \"{}\" which is not a target", target.name, parent));
+            }
+        }
+
+        // Walk up through the parents, meeting the target again means a loop
+        let mut stack: Vec<&String> = target.depends_on.iter().collect();
+        let mut seen: Vec<&String> = Vec::new();
+        while let Some(name) = stack.pop() {
+            if *name == target.name {
+                return Err(format!("{}: depends on itself", target.name));
+            }
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name);
+            if let Some(parent) = targets.iter().find(|other| other.name == *name) {
+                stack.extend(parent.depends_on.iter());
+            }
+        }
+    }
+    Ok(())
+}
+
+// A function that will read the config file, no config file means no preset targets
+pub fn load_config(path: &str) -> Result<Config, Box<dyn Error>> {
+    if !Path::new(path).exists() {
+        return Ok(Config::default());
+    }
+
+    let data = fs::read_to_string(path)?;
+    let mut config: Config = serde_json::from_str(&data)?;
+
+    Zone::from_name(config.timezone.as_deref())?;
+    Zone::from_name(config.report.timezone.as_deref())?;
+
+    if config.listen.parse::<SocketAddr>().is_err() {
+        return Err(format!("invalid listen address \"{}\"", config.listen).into());
+    }
+
+    if parse_duration(&config.stats_range).is_none() {
+        return Err(format!("invalid stats_range \"{}\"", config.stats_range).into());
+    }
+    if parse_duration(&config.canary_every).is_none() {
+        return Err(format!("invalid canary_every \"{}\"", config.canary_every).into());
+    }
+    for canary in &config.canaries {
+        if canary.parse::<Uri>().is_err() {
+            return Err(format!("invalid canary \"{}\"", canary).into());
+        }
+    }
+
+    for (name, notifier) in &config.notifiers {
+        let expanded = notifier.expanded().map_err(|err| format!("notifier {}: {}", name, err))?;
+        if expanded.url().parse::<Uri>().is_err() {
+            return Err(format!("notifier {}: invalid url \"{}\"", name, notifier.url()).into());
+        }
+    }
+    for name in &config.report.notify {
+        if !config.notifiers.contains_key(name) {
+            return Err(format!("report: unknown notifier \"{}\"", name).into());
+        }
+    }
+
+    if let Some(every) = &config.report.every {
+        if parse_duration(every).is_none() {
+            return Err(format!("report: invalid every \"{}\"", every).into());
+        }
+    }
+    if let Some(daily_at) = &config.report.daily_at {
+        if NaiveTime::parse_from_str(daily_at, "%H:%M").is_err() {
+            return Err(format!("report: invalid daily_at \"{}\"", daily_at).into());
+        }
+    }
+
+    let zone = config.zone();
+    for target in &config.targets {
+        if !config.daemon && !target.schedule.has_end() {
+            return Err(format!("{}: needs a schedule end when daemon is off", target.name).into());
+        }
+        if let Err(err) = check_window(&target.schedule, zone) {
+            return Err(format!("{}: {}", target.name, err).into());
+        }
+        if !config.daemon && target.schedule.window.repeat != Repeat::Once {
+            return Err(format!("{}: a repeating window needs daemon mode", target.name).into());
+        }
+        match (target.kind, &target.heartbeat) {
+            (Kind::Heartbeat, None) => return Err(format!("{}: a heartbeat target needs heartbeat", target.name).into()),
+            (Kind::Heartbeat, Some(heartbeat)) => {
+                if parse_duration(&heartbeat.period).is_none() {
+                    return Err(format!("{}: invalid heartbeat period \"{}\"", target.name, heartbeat.period).into());
+                }
+                if parse_duration(&heartbeat.grace).is_none() {
+                    return Err(format!("{}: invalid heartbeat grace \"{}\"", target.name, heartbeat.grace).into());
+                }
+            }
+            (_, Some(_)) => return Err(format!("{}: heartbeat needs \"kind\": \"heartbeat\"", target.name).into()),
+            (_, None) => {
+                if target.url.is_empty() && target.steps.is_empty() {
+                    return Err(format!("{}: needs a url", target.name).into());
+                }
+            }
+        }
+        for period in &target.maintenance {
+            if period.start.is_none() || (period.end.is_none() && period.duration.is_none()) {
+                return Err(format!("{}: a maintenance window needs a start and an end or a duration", target.name).into());
+            }
+            if let Err(err) = check_period(period, zone) {
+                return Err(format!("{}: maintenance, {}", target.name, err).into());
+            }
+        }
+        if parse_duration(&target.schedule.interval).is_none() {
+            return Err(format!("{}: invalid interval \"{}\"", target.name, target.schedule.interval).into());
+        }
+        if !target.schedule.jitter.is_empty() && parse_duration(&target.schedule.jitter).is_none() {
+            return Err(format!("{}: invalid jitter \"{}\"", target.name, target.schedule.jitter).into());
+        }
+        if parse_duration(&target.timeout).is_none() {
+            return Err(format!("{}: invalid timeout \"{}\"", target.name, target.timeout).into());
+        }
+        if parse_duration(&target.policy.backoff).is_none() {
+            return Err(format!("{}: invalid backoff \"{}\"", target.name, target.policy.backoff).into());
+        }
+        if parse_duration(&target.policy.flap_window).is_none() {
+            return Err(format!("{}: invalid flap_window \"{}\"", target.name, target.policy.flap_window).into());
+        }
+        if target.policy.failures == 0 {
+            return Err(format!("{}: failures must be at least 1", target.name).into());
+        }
+        if parse_duration(&target.cert.check_every).is_none() {
+            return Err(format!("{}: invalid check_every \"{}\"", target.name, target.cert.check_every).into());
+        }
+        if let (Some(warning), Some(critical)) = (target.latency.warning_ms, target.latency.critical_ms) {
+            if warning >= critical {
+                return Err(format!("{}: warning_ms must be lower than critical_ms", target.name).into());
+            }
+        }
+        for name in &target.notify {
+            if !config.notifiers.contains_key(name) {
+                return Err(format!("{}: unknown notifier \"{}\"", target.name, name).into());
+            }
+        }
+        if let Some(anomaly) = &target.anomaly {
+            if target.kind == Kind::Heartbeat {
+                return Err(format!("{}: a heartbeat has no latency to learn", target.name).into());
+            }
+            if anomaly.threshold <= 0.0 || anomaly.consecutive == 0 || anomaly.min_samples < 2 {
+                return Err(format!("{}: anomaly needs a threshold above 0, consecutive of at least 1 and min_samples of at least 2", target.name).into());
+            }
+            if !(anomaly.alpha > 0.0 && anomaly.alpha <= 1.0) {
+                return Err(format!("{}: anomaly alpha must be above 0 and at most 1", target.name).into());
+            }
+            if anomaly.history < anomaly.min_samples {
+                return Err(format!("{}: anomaly history must be at least min_samples", target.name).into());
+            }
+        }
+        if Method::from_bytes(target.request.method.as_bytes()).is_err() {
+            return Err(format!("{}: invalid method \"{}\"", target.name, target.request.method).into());
+        }
+        if target.request.body.is_some() && target.request.json.is_some() {
+            return Err(format!("{}: use either body or json, not both", target.name).into());
+        }
+        if let Err(err) = expand_env(&target.url).and_then(|_| target.request.check_env()) {
+            return Err(format!("{}: {}", target.name, err).into());
+        }
+        for spec in &target.expect.status {
+            if status_matches(spec, 200).is_none() {
+                return Err(format!("{}: invalid status \"{}\"", target.name, spec).into());
+            }
+        }
+        if !target.steps.is_empty() && target.kind != Kind::Http {
+            return Err(format!("{}: steps can only be used on websites", target.name).into());
+        }
+        for step in &target.steps {
+            if step.url.is_empty() {
+                return Err(format!("{}: step {} needs a url", target.name, step.name).into());
+            }
+            if Method::from_bytes(step.request.method.as_bytes()).is_err() {
+                return Err(format!("{}: step {}: invalid method \"{}\"", target.name, step.name, step.request.method).into());
+            }
+            if step.request.body.is_some() && step.request.json.is_some() {
+                return Err(format!("{}: step {}: use either body or json, not both", target.name, step.name).into());
+            }
+            if let Err(err) = expand_env(&step.url).and_then(|_| step.request.check_env()) {
+                return Err(format!("{}: step {}: {}", target.name, step.name, err).into());
+            }
+            for spec in &step.expect.status {
+                if status_matches(spec, 200).is_none() {
+                    return Err(format!("{}: step {}: invalid status \"{}\"", target.name, step.name, spec).into());
+                }
+            }
+        }
+        if target.content.is_some() && target.kind != Kind::Http {
+            return Err(format!("{}: content can only be watched on websites", target.name).into());
+        }
+    }
+
+    check_dependencies(&config.targets)?;
+
+    for target in config.targets.iter_mut() {
+        if let Err(err) = target.compile() {
+            return Err(format!("{}: {}", target.name, err).into());
+        }
+    }
+
+    // A one-off maintenance that is already over is left out, it can stay in the file for the record
+    let now = Utc::now();
+    for target in config.targets.iter_mut() {
+        let (over, planned): (Vec<Period>, Vec<Period>) = target.maintenance
+            .drain(..)
+            .partition(|period| is_over(period, zone, now));
+        target.maintenance = planned;
+        for period in over {
+            config.warnings.push(format!("{}: skipped maintenance {}, it is already over", target.name, describe_period(&period)));
+        }
+    }
+
+    // A flow is shown and its certificate read by the url of its first step
+    for target in config.targets.iter_mut() {
+        if target.url.is_empty() {
+            if let Some(step) = target.steps.first() {
+                target.url = step.url.clone();
+            }
+        }
+    }
+
+    // Every heartbeat job gets its own url, a heartbeat that is late is DOWN straight away as the grace already waited
+    let mut tokens: Vec<String> = Vec::new();
+    for target in config.targets.iter_mut() {
+        if let Some(heartbeat) = target.heartbeat.as_mut() {
+            if heartbeat.token.is_empty() {
+                heartbeat.token = target.name.clone();
+            }
+            if !heartbeat.token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(format!("{}: a heartbeat token can only have letters, digits, - and _", target.name).into());
+            }
+            if tokens.contains(&heartbeat.token) {
+                return Err(format!("{}: heartbeat token \"{}\" is used twice", target.name, heartbeat.token).into());
+            }
+            tokens.push(heartbeat.token.clone());
+
+            target.url = format!("http://{}/ping/{}", config.listen, heartbeat.token);
+            target.policy.failures = 1;
+            target.policy.retries = 0;
+        }
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,72 @@
+use scraper::Html;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crate::config::Content;
+use crate::helpers::target_file;
+
+// A function that will cut the watched region out of a body, the whole body when no region is set
+fn region(rule: &Content, body: &str) -> String {
+    let mut text = body.to_string();
+
+    if let Some(selector) = &rule.selector_compiled {
+        let document = Html::parse_document(&text);
+        text = document
+            .select(selector)
+            .map(|element| element.text().collect::<Vec<&str>>().join("\n"))
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+
+    if let Some(regex) = &rule.regex_compiled {
+        text = match regex.captures(&text) {
+            Some(captures) => captures.get(1).or(captures.get(0)).map(|found| found.as_str().to_string()).unwrap_or_default(),
+            None => String::new(),
+        };
+    }
+
+    text
+}
+
+// A function that will give back the watched text of a body with the whitespace cleaned up so only real changes count
+pub fn normalize(rule: &Content, body: &str) -> String {
+    region(rule, body)
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn content_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// A function that will show what changed between two versions of the text, lines starting with - are gone and + are new
+pub fn text_diff(old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header("before", "after")
+        .to_string()
+}
+
+// The file that keeps the last watched text of a target, so a change made while the app was stopped is still seen
+fn baseline_path(target: &str) -> PathBuf {
+    target_file("output/content", target, "txt")
+}
+
+// A function that will read the text that was watched the last time, None when the target was never watched before
+pub fn load_baseline(target: &str) -> Option<String> {
+    fs::read_to_string(baseline_path(target)).ok()
+}
+
+pub fn save_baseline(target: &str, text: &str) -> io::Result<()> {
+    fs::create_dir_all("output/content")?;
+    fs::write(baseline_path(target), text)
+}
@@ -0,0 +1,79 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use crate::config::Target;
+use crate::probe::{Probe, ProbeError, Timing};
+
+// When every heartbeat token was last pinged, and why the listener isn't running when it couldn't start
+#[derive(Default)]
+pub struct Pings {
+    pub last: HashMap<String, DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+pub type SharedPings = Arc<Mutex<Pings>>;
+
+// A function that will answer one request, only the request line is read e.g. "GET /ping/backup HTTP/1.1"
+async fn answer(mut stream: TcpStream, tokens: Vec<String>, pings: SharedPings) {
+    let mut buffer = [0; 1024];
+    let read = match tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await {
+        Ok(Ok(read)) => read,
+        _ => return,
+    };
+
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.lines().next().and_then(|line| line.split_whitespace().nth(1)).unwrap_or("");
+    let token = path
+        .strip_prefix("/ping/")
+        .map(|token| token.split('?').next().unwrap_or("").trim_end_matches('/'));
+
+    let response = match token {
+        Some(token) if tokens.iter().any(|known| known == token) => {
+            pings.lock().unwrap().last.insert(token.to_string(), Utc::now());
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nOK"
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nNot Found",
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+// A function that will take the pings of heartbeat jobs, a job calls /ping/<token> when it has finished
+pub async fn listen(address: String, tokens: Vec<String>, pings: SharedPings) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            pings.lock().unwrap().error = Some(format!("can't listen on {}: {}", address, err));
+            return;
+        }
+    };
+
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(answer(stream, tokens.clone(), pings.clone()));
+        }
+    }
+}
+
+// A function that will tell whether a heartbeat came in time, a job that never pinged is counted from when checking started
+pub fn check_heartbeat(target: &Target, pings: &SharedPings, started: DateTime<Utc>) -> Probe {
+    let heartbeat = target.heartbeat.as_ref().unwrap();
+    let pings = pings.lock().unwrap();
+    if let Some(error) = &pings.error {
+        return Probe::failed(Timing::default(), ProbeError::Other, error.clone());
+    }
+
+    let last = pings.last.get(&heartbeat.token).copied();
+    let waited = (Utc::now() - last.unwrap_or(started)).to_std().unwrap_or_default();
+    if waited <= heartbeat.allowed() {
+        return Probe::passed(Timing::default());
+    }
+
+    let rule = match last {
+        Some(last) => format!("no ping since {}", last.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        None => format!("no ping in {} + {}", heartbeat.period, heartbeat.grace),
+    };
+    Probe::failed(Timing::default(), ProbeError::Timeout, rule)
+}
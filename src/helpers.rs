@@ -1,16 +1,30 @@
 use isahc::{http::StatusCode, HttpClient};
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
-use reqwest::{blocking::Client, Error, header};
-use serde_json::json;
-use tokio::time::{Duration, Instant};
-use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::{prelude::*, TimeZone};
-use chrono_tz::Asia::Bangkok;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use rand::Rng;
+use chrono::{DateTime, SecondsFormat, Utc};
 use csv::Writer;
+use crate::anomaly::Detector;
+use crate::canary::{watch, Canaries};
+use crate::cert::*;
+use crate::content::{content_hash, load_baseline, save_baseline, text_diff};
+use crate::config::{parse_duration, Kind, Repeat, Settings, Target, Zone, MAX_THROTTLE_WAIT, STATS_RANGES};
+use crate::heartbeat::{check_heartbeat, listen, Pings, SharedPings};
+use crate::window::{is_open, next_window};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::Notify;
+use crate::monitor::*;
+use crate::probe::*;
+use crate::notify::{AlertEvent, EventKind, Notifiers};
+use crate::stats::{compute, Sample, Stats};
 
-// A funtion that will check internet connection
-pub fn is_internet_connected() -> bool {
+// A funtion that will check internet connection, it is connected when any of the canaries answers
+pub fn is_internet_connected(canaries: &[String]) -> bool {
     let client = match HttpClient::new() {
         Ok(client) => client,
         _ => {
@@ -18,17 +32,13 @@ pub fn is_internet_connected() -> bool {
         }
     };
 
-    /* You can replace any website here but for ensure it's
+    /* The canaries are set in config.json, for ensure it's
     work probably you should choose website that reliable
     e.g. Google, Facebook, Amazon, etc. */
-    let response = match client.get("https://www.google.com") {
-        Ok(response) => response,
-        _ => {
-            return false;
-        }
-    };
-
-    response.status() == StatusCode::OK || response.status().is_redirection()
+    canaries.is_empty() || canaries.iter().any(|canary| match client.get(canary.as_str()) {
+        Ok(response) => response.status() == StatusCode::OK || response.status().is_redirection(),
+        _ => false,
+    })
 }
 
 pub fn make_env(recipient_name: String, recipient_email: String) {
@@ -45,35 +55,73 @@ SENDER_EMAIL=\"\"
     data_file.write(env.as_bytes()).expect("Nothing");
 }
 
-use std::env;
-
+#[derive(Serialize, Deserialize)]
 pub struct Time {
+    target: String,
     real_time: String,
+    // the total time of the probe
     response_time: i32,
+    dns_time: i32,
+    connect_time: i32,
+    tls_time: i32,
+    ttfb: i32,
+    state: State,
+    // probed during a maintenance window, left out of the uptime
+    maintenance: bool,
+    error: Option<ProbeError>,
+    failed_rule: String,
+    // only filled in on the probes that also read the certificate
+    cert_days: Option<i32>,
+    // why reading the certificate failed on such a probe
+    cert_error: String,
+    // the hash of the watched content, empty when the content is not watched
+    content_hash: String,
+    // the parent that was down, this sample didn't alert because of it
+    root_cause: String,
+    // the time of each step of a flow e.g. "login=120|profile=80"
+    steps: String,
+    // every redirect that was followed with its status, Location and time
+    redirects: String,
+    final_url: String,
+    // the latency is off what is normal for the target at this hour
+    anomaly: bool,
 }
 
-pub struct User {
-    name: String,
-    email: String,
+// How long a target spent in one state
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StateTime {
+    target: String,
+    state: State,
+    seconds: u64,
 }
 
-// A function that will send an email
-pub fn send_email(result: bool) -> Result<(), Error> {
-    dotenv::dotenv().ok();
-
-    let api_key = env::var("SENDGRID_API_KEY").unwrap();
-
-    let sender = User {
-        name: String::from(env::var("SENDER_NAME").unwrap()),
-        email: String::from(env::var("SENDER_EMAIL").unwrap()),
-    };
+// Text from a response can hold anything so it is escaped before it goes into an email
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
 
-    let recipient = User {
-        name: String::from(env::var("RECIPIENT_NAME").unwrap()),
-        email: String::from(env::var("RECIPIENT_EMAIL").unwrap()),
-    };
+// A function that will start an event that happened just now, the kind decides the rest
+fn new_event(kind: EventKind, target: &str, title: String, text: String, html: String) -> AlertEvent {
+    AlertEvent {
+        kind,
+        target: target.to_string(),
+        old_state: None,
+        new_state: None,
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        title,
+        text,
+        stats: None,
+        html,
+    }
+}
 
-    let graph = read_csv();
+// A function that will build the report of every target
+pub fn report_event(result: bool, zone: Zone, since: Option<DateTime<Utc>>) -> AlertEvent {
+    let graph = read_csv(zone);
+    let stats = read_stats(since);
+    let timings = read_timings();
+    let certs = read_certs();
+    let states = read_states();
     let email_template_success = format!("<body>
     
     <h1>Email Report</h1>
@@ -82,12 +130,21 @@ pub fn send_email(result: bool) -> Result<(), Error> {
 
     <p>This is your performance(response time) report for the website</p>
     <ul>
-        <li>Website response times have a good status!</li>
+        <li>Every website ended the check UP</li>
     </ul>
 
+    <p>Time spent in each state</p>
+    {}
+
+    {}
+
+    {}
+
+    {}
+
     <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
 
-</body>", graph);
+</body>", graph, states, stats, timings, certs);
 
     let email_template_unsuccess = format!("<body>
 
@@ -96,181 +153,974 @@ pub fn send_email(result: bool) -> Result<(), Error> {
     <p>This is your performance(response time) report for the website</p>
 
     <ul>
-        <li>Website response have a bad status!</li>
+        <li>Some websites did not end the check UP</li>
     </ul>
 
+    <p>Time spent in each state</p>
+    {}
+
+    {}
+
+    {}
+
+    {}
+
     <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
 
-</body>");
+</body>", states, stats, timings, certs);
 
-    if result == true {
-        let body = json!(
-            {
-                "personalizations": [{
-                    "to": [{
-                        "email": recipient.email,
-                        "name": recipient.name
-                    }],
-    
-                    "subject": "Website Performance Report"
-                }],
-                "from": {
-                    "email": sender.email,
-                    "name": sender.name
-                },
-                "subject": "",
-                "content": [
-                    {
-                        "type": "text/html",
-                        "value": email_template_success,
-                    },
-                ]
+    let (text, html) = if result == true {
+        ("Every website ended the check UP", email_template_success)
+    }
+    else {
+        ("Some websites did not end the check UP", email_template_unsuccess)
+    };
+    new_event(EventKind::Report, "", "Website Performance Report".to_string(), text.to_string(), html)
+}
+
+// A function that will build the alert for a target that changed its state
+pub fn alert_event(target: &str, old_state: State, new_state: State, reason: &str, dependents: &[String], stats: Stats) -> AlertEvent {
+    let mut text = format!("{} has changed from {} to {}", target, old_state, new_state);
+    if !reason.is_empty() {
+        text.push_str(format!("\nLast failure: {}", reason).as_str());
+    }
+    let mut reason = if reason.is_empty() {
+        String::new()
+    }
+    else {
+        format!("<p>Last failure: {}</p>", escape_html(reason))
+    };
+    // The targets behind this one don't send their own alerts while it is down
+    if matches!(new_state, State::Down | State::Flapping) && !dependents.is_empty() {
+        reason.push_str(format!("\n    <p>This is the root cause for: {}</p>", dependents.join(", ")).as_str());
+        text.push_str(format!("\nThis is the root cause for: {}", dependents.join(", ")).as_str());
+    }
+    let email_template_alert = format!("<body>
+
+    <h1>Incident Report</h1>
+
+    <p><strong>{}</strong> has changed from <strong>{}</strong> to <strong>{}</strong></p>
+
+    {}
+
+    <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
+
+</body>", target, old_state, new_state, reason);
+
+    AlertEvent {
+        old_state: Some(old_state),
+        new_state: Some(new_state),
+        stats: Some(stats),
+        ..new_event(EventKind::StateChange, target, format!("{} is {}", target, new_state), text, email_template_alert)
+    }
+}
+
+// A function that will build the warning for a certificate that gets close to running out
+pub fn cert_event(cert: &CertInfo, threshold: i32) -> AlertEvent {
+    let email_template_cert = format!("<body>
+
+    <h1>Certificate Warning</h1>
+
+    <p>The certificate of <strong>{}</strong> runs out in <strong>{} days</strong> (less than {} days)</p>
+
+    <ul>
+        <li>Subject: {}</li>
+        <li>Issuer: {}</li>
+        <li>Names: {}</li>
+        <li>Runs out first: {}</li>
+        <li>Expires: {}</li>
+    </ul>
+
+    <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
+
+</body>", cert.target, cert.days_left, threshold, cert.subject, cert.issuer, cert.sans, cert.expires_first, cert.not_after);
+
+    let text = format!("The certificate of {} runs out in {} days (less than {} days), {} expires {}", cert.target, cert.days_left, threshold, cert.expires_first, cert.not_after);
+    let title = format!("Certificate of {} runs out in {} days", cert.target, cert.days_left);
+    new_event(EventKind::Certificate, &cert.target, title, text, email_template_cert)
+}
+
+// A function that will build the message with what changed on a watched page
+pub fn content_event(target: &str, diff: &str) -> AlertEvent {
+    let text = diff.to_string();
+    let diff = escape_html(diff);
+    let email_template_content = format!("<body>
+
+    <h1>Content Changed</h1>
+
+    <p>The watched content of <strong>{}</strong> has changed</p>
+
+    <pre>{}</pre>
+
+    <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
+
+</body>", target, diff);
+
+    new_event(EventKind::ContentChange, target, format!("Content of {} has changed", target), text, email_template_content)
+}
+
+// A function that will build the message for redirects of a target that are not the same as on the probe before
+pub fn redirect_event(target: &str, old: &str, new: &str) -> AlertEvent {
+    let show = |chain: &str| if chain.is_empty() { "no redirects".to_string() } else { escape_html(&chain.replace(" | ", "\n")) };
+    let email_template_content = format!("<body>
+
+    <h1>Redirects Changed</h1>
+
+    <p>The redirect chain of <strong>{}</strong> has changed</p>
+
+    <p>Before</p>
+    <pre>{}</pre>
+
+    <p>Now</p>
+    <pre>{}</pre>
+
+    <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
+
+</body>", target, show(old), show(new));
+
+    let plain = |chain: &str| if chain.is_empty() { "no redirects".to_string() } else { chain.to_string() };
+    let text = format!("Before: {}\nNow: {}", plain(old), plain(new));
+    new_event(EventKind::RedirectChange, target, format!("Redirects of {} have changed", target), text, email_template_content)
+}
+
+// A function that will build the message for a target whose latency is off its normal while it still answers
+pub fn anomaly_event(target: &str, description: &str) -> AlertEvent {
+    let email_template_content = format!("<body>
+
+    <h1>Latency Anomaly</h1>
+
+    <p><strong>{}</strong> still answers but its latency is not normal for this time of the day</p>
+
+    <p>{}</p>
+
+    <p>from <strong><i>notifychecker</i></strong>, made with 💖 by <strong>@serayuta</strong></p>
+
+</body>", target, escape_html(description));
+
+    new_event(EventKind::Anomaly, target, format!("Latency anomaly on {}", target), description.to_string(), email_template_content)
+}
+
+// A function that will read every sample back from the .csv file
+fn read_times() -> Vec<Time> {
+    let reader = csv::Reader::from_path("output/test.csv");
+    reader.expect("NOTHING").deserialize().map(|result| result.unwrap()).collect()
+}
+
+impl Time {
+    fn sample(&self) -> Sample {
+        Sample {
+            at: DateTime::parse_from_rfc3339(&self.real_time).map(|at| at.with_timezone(&Utc)).unwrap_or_default(),
+            response_time: self.response_time,
+            state: self.state,
+            maintenance: self.maintenance,
+            failed: self.error.is_some(),
+        }
+    }
+}
+
+// A function that will build a table of the uptime, latency and incidents of every target since a time, or of every sample
+pub fn read_stats(since: Option<DateTime<Utc>>) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let mut samples: Vec<Vec<Sample>> = Vec::new();
+
+    for time in read_times() {
+        let index = match names.iter().position(|name| *name == time.target) {
+            Some(index) => index,
+            None => {
+                names.push(time.target.clone());
+                samples.push(Vec::new());
+                names.len() - 1
             }
-        );
-
-        let client = Client::new()
-        .post("https://api.sendgrid.com/v3/mail/send")
-        .json(&body)
-        .bearer_auth(api_key)
-        .header(
-            header::CONTENT_TYPE, 
-            header::HeaderValue::from_static("application/json")
-        );
-        let _ = client.send()?;
+        };
+        samples[index].push(time.sample());
+    }
+
+    let mut table = String::from("<table border=\"1\" cellpadding=\"4\">
+        <tr><th>Target</th><th>Uptime</th><th>Mean (ms)</th><th>Min</th><th>Max</th><th>p50</th><th>p90</th><th>p95</th><th>p99</th><th>Incidents</th><th>MTTR</th></tr>");
+    for (name, samples) in names.iter().zip(samples) {
+        let stats = compute(&samples, since, None);
+        table.push_str(format!("
+        <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            name, show_uptime(&stats), stats.mean, stats.min, stats.max, stats.p50, stats.p90, stats.p95, stats.p99,
+            stats.incidents, stats.mttr.map(format_seconds).unwrap_or("-".to_string())).as_str());
+    }
+    table.push_str("
+    </table>");
+
+    table
+}
+
+// A function that will work out how long to leave a throttled target alone, what it asked for but at least the interval, or a doubling backoff from the interval when it didn't say
+pub fn throttle_wait(retry_after: Option<Duration>, interval: Duration, streak: u32) -> Duration {
+    let wait = match retry_after {
+        Some(wait) => wait.max(interval),
+        None => interval.saturating_mul(2u32.saturating_pow(streak.min(31))),
+    };
+    wait.min(MAX_THROTTLE_WAIT)
+}
+
+// A function that will give the file a target keeps something in, characters that don't belong in a file name become _
+pub fn target_file(dir: &str, target: &str, extension: &str) -> PathBuf {
+    let name: String = target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    Path::new(dir).join(format!("{}.{}", name, extension))
+}
+
+// A function that will give the moment a range that ends now starts at, None when it reaches back further than time goes
+pub fn since(range: Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(range).ok().and_then(|range| Utc::now().checked_sub_signed(range))
+}
+
+fn show_uptime(stats: &Stats) -> String {
+    match stats.uptime {
+        Some(uptime) => format!("{:.2}%", uptime),
+        None => "-".to_string(),
+    }
+}
+
+// A function that will write the statistics of a target on one line for the TUI
+pub fn describe_stats(stats: &Stats) -> String {
+    let mut text = format!("uptime {} | p50 {} p90 {} p95 {} p99 {} ms | mean {} min {} max {} ms | {} incidents",
+        show_uptime(stats), stats.p50, stats.p90, stats.p95, stats.p99, stats.mean, stats.min, stats.max, stats.incidents);
+    if let Some(mttr) = stats.mttr {
+        text.push_str(format!(", MTTR {}", format_seconds(mttr)).as_str());
+    }
+    text
+}
+
+// A function that will build a table of the average time of each part of a probe per target
+pub fn read_timings() -> String {
+    let mut names: Vec<String> = Vec::new();
+    let mut sums: Vec<[i64; 6]> = Vec::new();
+
+    for time in read_times() {
+        let index = match names.iter().position(|name| *name == time.target) {
+            Some(index) => index,
+            None => {
+                names.push(time.target.clone());
+                sums.push([0; 6]);
+                names.len() - 1
+            }
+        };
+        let parts = [time.dns_time, time.connect_time, time.tls_time, time.ttfb, time.response_time];
+        for (sum, part) in sums[index].iter_mut().zip(parts) {
+            *sum += part as i64;
+        }
+        sums[index][5] += 1;
+    }
+
+    let mut table = String::from("<table border=\"1\" cellpadding=\"4\">
+        <tr><th>Target</th><th>DNS (ms)</th><th>Connect (ms)</th><th>TLS (ms)</th><th>TTFB (ms)</th><th>Total (ms)</th></tr>");
+    for (name, sum) in names.iter().zip(sums) {
+        let count = sum[5].max(1);
+        table.push_str(format!("
+        <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            name, sum[0] / count, sum[1] / count, sum[2] / count, sum[3] / count, sum[4] / count).as_str());
+    }
+    table.push_str("
+    </table>");
+
+    table
+}
+
+// A function that will turn seconds into "1h 5m 30s"
+fn format_seconds(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    }
+    else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
     }
     else {
-        let body = json!(
-            {
-                "personalizations": [{
-                    "to": [{
-                        "email": recipient.email,
-                        "name": recipient.name
-                    }],
-    
-                    "subject": "Website Performance Report"
-                }],
-                "from": {
-                    "email": sender.email,
-                    "name": sender.name
-                },
-                "subject": "",
-                "content": [
-                    {
-                        "type": "text/html",
-                        "value": email_template_unsuccess,
-                    },
-                ]
+        format!("{}s", seconds)
+    }
+}
+
+// A function that will build a list of how long every target spent in each state
+pub fn read_states() -> String {
+    let reader = csv::Reader::from_path("output/states.csv");
+    let state_times: Vec<StateTime> = match reader {
+        Ok(mut reader) => reader.deserialize().filter_map(|result| result.ok()).collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    let mut lines: Vec<Vec<String>> = Vec::new();
+    for state_time in state_times {
+        let index = match names.iter().position(|name| *name == state_time.target) {
+            Some(index) => index,
+            None => {
+                names.push(state_time.target.clone());
+                lines.push(Vec::new());
+                names.len() - 1
             }
-        );
+        };
+        lines[index].push(format!("{} {}", state_time.state, format_seconds(state_time.seconds)));
+    }
 
-        let client = Client::new()
-        .post("https://api.sendgrid.com/v3/mail/send")
-        .json(&body)
-        .bearer_auth(api_key)
-        .header(
-            header::CONTENT_TYPE, 
-            header::HeaderValue::from_static("application/json")
-        );
-        let _ = client.send()?;
+    let mut list = String::from("<ul>");
+    for (name, line) in names.iter().zip(lines) {
+        list.push_str(format!("
+        <li><strong>{}</strong>: {}</li>", name, line.join(", ")).as_str());
     }
+    list.push_str("
+    </ul>");
 
-    Ok(())
+    list
 }
 
-// A function that will read .csv files
-pub fn read_csv() -> String {
-    // Read data from the CSV file and format it
-    let mut x_values = Vec::new();
-    let mut y_values = Vec::new();
+// A function that will build a table of the certificates of the https targets
+pub fn read_certs() -> String {
+    let reader = csv::Reader::from_path("output/certs.csv");
+    let certs: Vec<CertInfo> = match reader {
+        Ok(mut reader) => reader.deserialize().filter_map(|result| result.ok()).collect(),
+        Err(_) => Vec::new(),
+    };
+    if certs.is_empty() {
+        return String::new();
+    }
 
-    let reader = csv::Reader::from_path("output/test.csv");
-    for result in reader.expect("NOTHING").records() {
-        let record = result.unwrap();
-        let time = &record[0];
-        let response_time: f64 = record[1].parse().unwrap();
-        x_values.push(time.to_string());
-        y_values.push(response_time);
+    let mut table = String::from("<table border=\"1\" cellpadding=\"4\">
+        <tr><th>Target</th><th>Subject</th><th>Issuer</th><th>Names</th><th>Runs out first</th><th>Expires</th><th>Days left</th></tr>");
+    for cert in certs {
+        table.push_str(format!("
+        <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            cert.target, cert.subject, cert.issuer, cert.sans, cert.expires_first, cert.not_after, cert.days_left).as_str());
     }
+    table.push_str("
+    </table>");
+
+    table
+}
+
+// A function that will read .csv files, the times are shown in the zone of the report
+pub fn read_csv(zone: Zone) -> String {
+    // Read data from the CSV file and format it, one series per target
+    let mut names: Vec<String> = Vec::new();
+    let mut x_values: Vec<Vec<String>> = Vec::new();
+    let mut y_values: Vec<Vec<f64>> = Vec::new();
+
+    for time in read_times() {
+        let index = match names.iter().position(|name| *name == time.target) {
+            Some(index) => index,
+            None => {
+                names.push(time.target.clone());
+                x_values.push(Vec::new());
+                y_values.push(Vec::new());
+                names.len() - 1
+            }
+        };
+        x_values[index].push(zone.show_saved(&time.real_time, "%H:%M"));
+        y_values[index].push(time.response_time as f64);
+    }
+
+    // Use the longest series for the time labels
+    let labels = x_values.iter().max_by_key(|x| x.len()).cloned().unwrap_or_default();
 
     // Construct the Google Charts API URL
     let chart_api_url = "https://chart.googleapis.com/chart?";
 
     // Define chart parameters and data
     let chart_type = "cht=lc"; // Line chart
-    let chart_data = format!("chd=t:{}", y_values.iter().map(|&v| v.to_string()).collect::<Vec<String>>().join(","));
+    let chart_data = format!("chd=t:{}", y_values.iter()
+        .map(|series| series.iter().map(|&v| v.to_string()).collect::<Vec<String>>().join(","))
+        .collect::<Vec<String>>()
+        .join("|"));
     let chart_size = "chs=400x200"; // Chart size
-    let chart_labels = format!("chxt=y,x&chxr=0,0,1000&chds=0,1000&chxl=1:|{}", x_values.join("|"));
+    let chart_labels = format!("chxt=y,x&chxr=0,0,1000&chds=0,1000&chxl=1:|{}", labels.join("|"));
+    let chart_legend = format!("chdl={}", names.join("|"));
 
     // Combine all parameters into the URL
-    let url = format!("{}{}&{}&{}&{}", chart_api_url, chart_type, chart_data, chart_size, chart_labels);
+    let url = format!("{}{}&{}&{}&{}&{}", chart_api_url, chart_type, chart_data, chart_size, chart_labels, chart_legend);
 
     return url;
 }
 
-// A function that will check response time and collect a data
+// What the TUI and the reports need to know about one target, kept up to date after every probe
+#[derive(Clone)]
+pub struct Summary {
+    pub target: String,
+    pub state: State,
+    pub last_time: String,
+    pub last_response: i32,
+    // when the next window opens, empty while the window is open
+    pub opens_at: String,
+    // in a maintenance window right now, from the config or switched on in the TUI
+    pub maintenance: bool,
+    pub manual_maintenance: bool,
+    pub depends_on: Vec<String>,
+    // the parent that is down right now, empty when all parents are fine
+    pub root_cause: String,
+    pub cert: Option<CertInfo>,
+    // why the last certificate check failed, empty when it worked
+    pub cert_error: String,
+    pub state_times: Vec<StateTime>,
+    // the statistics of every range the TUI can switch to, worked out by the target after each probe
+    pub stats: BTreeMap<String, Stats>,
+    // the latency has been off its normal for a while
+    pub anomaly: bool,
+    // when the target asked us to wait until, empty when it didn't
+    pub next_probe: String,
+    // why the last alert of the target could not be sent, empty when it went out
+    pub notify_error: String,
+}
+
+pub type Summaries = Arc<Mutex<Vec<Summary>>>;
+type Samples = Arc<Mutex<Writer<File>>>;
+
+fn manual_maintenance(summaries: &Summaries, target: &str) -> bool {
+    let summaries = summaries.lock().unwrap();
+    summaries.iter().any(|summary| summary.target == target && summary.manual_maintenance)
+}
+
+// A function that will find the parent that is down furthest up the chain, the down parent whose own parents are all fine is the root cause
+pub fn find_root_cause(summaries: &Summaries, target: &str) -> Option<String> {
+    let summaries = summaries.lock().unwrap();
+    let down_parents = |name: &str| -> Vec<String> {
+        summaries
+            .iter()
+            .find(|summary| summary.target == name)
+            .map(|summary| summary.depends_on
+                .iter()
+                .filter(|parent| summaries
+                    .iter()
+                    .any(|other| other.target == **parent && matches!(other.state, State::Down | State::Flapping)))
+                .cloned()
+                .collect())
+            .unwrap_or_default()
+    };
+
+    // The parents listed first are looked at first
+    let mut stack: Vec<String> = down_parents(target).into_iter().rev().collect();
+    let mut seen: Vec<String> = Vec::new();
+    while let Some(name) = stack.pop() {
+        if seen.contains(&name) {
+            continue;
+        }
+        let parents = down_parents(&name);
+        if parents.is_empty() {
+            return Some(name);
+        }
+        stack.extend(parents.into_iter().rev());
+        seen.push(name);
+    }
+
+    None
+}
+
+// The targets that sit right behind a target
+fn dependents_of(summaries: &Summaries, target: &str) -> Vec<String> {
+    summaries
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|summary| summary.depends_on.iter().any(|parent| parent == target))
+        .map(|summary| summary.target.clone())
+        .collect()
+}
+
+fn update_summary<F: FnOnce(&mut Summary)>(summaries: &Summaries, target: &str, update: F) {
+    let mut summaries = summaries.lock().unwrap();
+    if let Some(summary) = summaries.iter_mut().find(|summary| summary.target == target) {
+        update(summary);
+    }
+}
+
+// A function that will write the files that the report email reads, gives back whether every target is UP
+fn write_report_files(samples: &Samples, summaries: &Summaries) -> bool {
+    let _ = samples.lock().unwrap().flush();
+
+    let summaries = summaries.lock().unwrap().clone();
+    // A target that is down for planned maintenance doesn't count as a failure
+    let result = summaries.iter().all(|summary| summary.state == State::Up || summary.maintenance);
+    let certs = summaries.iter().filter_map(|summary| summary.cert.clone()).collect();
+    let state_times = summaries.into_iter().flat_map(|summary| summary.state_times).collect();
+
+    let _ = write_certs(File::create("output/certs.csv").unwrap(), certs);
+    let _ = write_states(File::create("output/states.csv").unwrap(), state_times);
+    result
+}
+
+// Runs check_res on its own thread so the TUI keeps on drawing while the targets are checked
+pub struct Monitor {
+    pub summaries: Summaries,
+    pub canaries: Arc<Canaries>,
+    stop: Arc<Notify>,
+    handle: thread::JoinHandle<bool>,
+}
+
+impl Monitor {
+    pub fn start(targets: Vec<Target>, settings: Settings) -> Monitor {
+        let summaries: Summaries = Arc::new(Mutex::new(targets
+            .iter()
+            .map(|target| Summary {
+                target: target.name.clone(),
+                state: State::Up,
+                last_time: String::new(),
+                last_response: 0,
+                opens_at: String::new(),
+                maintenance: false,
+                manual_maintenance: false,
+                depends_on: target.depends_on.clone(),
+                root_cause: String::new(),
+                cert: None,
+                cert_error: String::new(),
+                state_times: Vec::new(),
+                stats: BTreeMap::new(),
+                anomaly: false,
+                next_probe: String::new(),
+                notify_error: String::new(),
+            })
+            .collect()));
+        let stop = Arc::new(Notify::new());
+        let canaries = Arc::new(Canaries::new(settings.canaries.clone()));
+
+        let handle = {
+            let summaries = summaries.clone();
+            let stop = stop.clone();
+            let canaries = canaries.clone();
+            thread::spawn(move || check_res(targets, settings, canaries, summaries, stop))
+        };
+
+        Monitor { summaries, canaries, stop, handle }
+    }
+
+    // Switch the maintenance of a target on or off from the TUI
+    pub fn toggle_maintenance(&self, index: usize) {
+        if let Some(summary) = self.summaries.lock().unwrap().get_mut(index) {
+            summary.manual_maintenance = !summary.manual_maintenance;
+        }
+    }
+
+    // Every target has reached its end time
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    // Stop checking and wait for the files to be written, gives back whether every target ended UP
+    pub fn finish(self) -> bool {
+        self.stop.notify_one();
+        self.handle.join().unwrap_or(false)
+    }
+}
+
+// A function that will check every target at the same time and collect a data
 #[tokio::main]
-pub async fn check_res(url: String, hour: String, minuite: String) -> bool {
-    let interval = Duration::from_secs(1); // Set the interval in seconds (e.g., 60 seconds)
+pub async fn check_res(targets: Vec<Target>, settings: Settings, canaries: Arc<Canaries>, summaries: Summaries, stop: Arc<Notify>) -> bool {
+    let samples: Samples = Arc::new(Mutex::new(Writer::from_writer(File::create("output/test.csv").unwrap())));
 
-    let mut list_time = Vec::new();
+    // Heartbeat jobs ping the app so it only listens when there are heartbeats to wait for
+    let pings: SharedPings = Arc::new(Mutex::new(Pings::default()));
+    let tokens: Vec<String> = targets
+        .iter()
+        .filter_map(|target| target.heartbeat.as_ref().map(|heartbeat| heartbeat.token.clone()))
+        .collect();
+    let listener = if tokens.is_empty() {
+        None
+    }
+    else {
+        Some(tokio::spawn(listen(settings.listen.clone(), tokens, pings.clone())))
+    };
 
-    loop {
-        let start_time = Instant::now();
+    // Keep an eye on our own network so a blip doesn't look like every target is down
+    let watcher = tokio::spawn(watch(canaries.clone(), settings.canary_every()));
 
-        // Get the current time as a `SystemTime` object.
-        let current_time = SystemTime::now();
+    // Every alert goes to the notifiers its target routes to
+    let notifiers = Arc::new(Notifiers::new(&settings.notifiers));
+
+    let mut handles = Vec::new();
+    for target in targets {
+        handles.push(tokio::spawn(check_target(target, settings.clone(), canaries.clone(), notifiers.clone(), pings.clone(), samples.clone(), summaries.clone())));
+    }
+
+    // The daemon never ends by itself so it sends its report on a schedule instead
+    let reporter = if settings.daemon {
+        Some(tokio::spawn(send_reports(settings.clone(), samples.clone(), summaries.clone())))
+    }
+    else {
+        None
+    };
 
-        // Calculate the duration since the UNIX epoch.
-        let duration = current_time.duration_since(UNIX_EPOCH).expect("SystemTime::duration_since failed");
+    tokio::select! {
+        _ = stop.notified() => {}
+        _ = async {
+            for handle in handles.iter_mut() {
+                let _ = handle.await;
+            }
+        } => {}
+    }
 
-        // Convert the duration to a `DateTime<Utc>` object.
-        let utc_time = Utc.timestamp_opt(duration.as_secs() as i64, duration.subsec_nanos());
+    for handle in &handles {
+        handle.abort();
+    }
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
+    if let Some(listener) = listener {
+        listener.abort();
+    }
+    watcher.abort();
 
-        // Convert the UTC time to the desired time zone (GMT+7).
-        let local_time = utc_time.unwrap().with_timezone(&Bangkok);
+    write_report_files(&samples, &summaries)
+}
 
-        // Format the date and time as a string.
-        let formatted_time = local_time.format("%H:%M").to_string();
+// A function that will keep a notifier failure in output/notify.log, an alert that was never sent is otherwise never seen
+fn log_notify_error(event: &AlertEvent, err: &str) {
+    let about = if event.target.is_empty() {
+        format!("{:?}", event.kind)
+    }
+    else {
+        format!("{:?} of {}", event.kind, event.target)
+    };
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open("output/notify.log") {
+        let _ = writeln!(file, "{} {} not sent: {}", event.time, about, err);
+    }
+}
 
-        if formatted_time == format!("{}:{}", hour, minuite) {
-            let _ = write_csv(File::create("output/test.csv").unwrap(), list_time);
-            break;
+// A function that will hand an event to the notifiers without holding up the probes, a failure is logged and shown next to the target
+fn notify(notifiers: &Arc<Notifiers>, summaries: &Summaries, route: &[String], event: AlertEvent) {
+    let (notifiers, summaries, route) = (notifiers.clone(), summaries.clone(), route.to_vec());
+    tokio::task::spawn_blocking(move || {
+        let result = notifiers.send(&route, &event);
+        if let Err(err) = &result {
+            log_notify_error(&event, err);
+        }
+        update_summary(&summaries, &event.target, |summary| summary.notify_error = result.err().unwrap_or_default());
+    });
+}
+
+// A function that will send the report to the notifiers of the report, `since` None covers every sample
+pub fn send_report(settings: &Settings, result: bool, since: Option<DateTime<Utc>>) -> Result<(), String> {
+    let event = report_event(result, settings.report_zone, since);
+    let result = Notifiers::new(&settings.notifiers).send(&settings.report.notify, &event);
+    if let Err(err) = &result {
+        log_notify_error(&event, err);
+    }
+    result
+}
+
+// A function that will send the report email on the report schedule, it never ends
+async fn send_reports(settings: Settings, samples: Samples, summaries: Summaries) {
+    // every report has the statistics since the report before
+    let mut since = Utc::now();
+    loop {
+        let wait = match (&settings.report.every, &settings.report.daily_at) {
+            (Some(every), _) => parse_duration(every).unwrap(),
+            (None, Some(daily_at)) => {
+                let (hour, minute) = daily_at.split_once(':').unwrap();
+                settings.zone.time_until(hour, minute)
+            }
+            (None, None) => return,
+        };
+        tokio::time::sleep(wait).await;
+
+        let result = write_report_files(&samples, &summaries);
+        let (report_settings, from) = (settings.clone(), since);
+        since = Utc::now();
+        let _ = tokio::task::spawn_blocking(move || send_report(&report_settings, result, Some(from))).await;
+    }
+}
+
+// A function that will check response time of one target and collect a data
+async fn check_target(target: Target, settings: Settings, canaries: Arc<Canaries>, notifiers: Arc<Notifiers>, pings: SharedPings, samples: Samples, summaries: Summaries) {
+    let (zone, stats_range) = (settings.zone, settings.stats_range());
+    let mut tracker = Tracker::new(target.policy.clone());
+    let client = build_client(&target);
+
+    let mut cert_watch = CertWatch::new(target.cert.warn_days.clone());
+    let mut last_cert_check: Option<Instant> = None;
+    // the state the target was in when its alerts started to be held back by maintenance or a parent that is down
+    let mut held_from: Option<State> = None;
+    let mut last_content: Option<String> = target.content.as_ref().and_then(|_| load_baseline(&target.name));
+    let mut last_chain: Option<String> = None;
+    // The normal latency is learned over days so it is kept across restarts
+    let baseline_file = target_file("output/anomaly", &target.name, "json");
+    let mut detector = target.anomaly.clone().map(|rule| Detector::load(rule, &baseline_file));
+    // the samples for the statistics in the TUI and in the alerts, they stay here and only the numbers go to the summary
+    let mut recent: Vec<Sample> = Vec::new();
+    let mut ranges: Vec<String> = STATS_RANGES.iter().map(|range| range.to_string()).collect();
+    if !ranges.contains(&settings.stats_range) {
+        ranges.push(settings.stats_range.clone());
+    }
+    let keep = ranges
+        .iter()
+        .filter_map(|range| parse_duration(range))
+        .fold(stats_range, Duration::max);
+
+    // A target is only checked while its window is open, a repeating window opens again after it closes
+    let period = target.schedule.period();
+    while let Some(window) = next_window(&period, zone, Utc::now()) {
+        let wait = (window.start - Utc::now()).to_std().unwrap_or_default();
+        if !wait.is_zero() {
+            let opens_at = window.start.to_rfc3339_opts(SecondsFormat::Secs, true);
+            update_summary(&summaries, &target.name, |summary| summary.opens_at = opens_at);
+            tokio::time::sleep(wait).await;
+            update_summary(&summaries, &target.name, |summary| summary.opens_at.clear());
         }
+        // a heartbeat job that hasn't pinged yet gets its period from when the window opened
+        let started = Utc::now();
+
+        // The scheduler fires once per interval, a probe that runs late pushes the next one back
+        let mut ticker = tokio::time::interval(target.schedule.interval());
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // how many probes in a row the target throttled
+        let mut throttle_streak: u32 = 0;
+
+        // A window without an end is checked until the app is stopped
+        let end = match window.end {
+            Some(end) => (end - Utc::now()).to_std().unwrap_or_default(),
+            None => Duration::MAX,
+        };
+        let end = tokio::time::sleep(end);
+        tokio::pin!(end);
+
+        loop {
+            tokio::select! {
+                _ = &mut end => break,
+                _ = ticker.tick() => {}
+            }
 
-        // Perform an HTTP GET request
-        match reqwest::get(url.clone()).await.unwrap().status() {
-            StatusCode::OK => {
-                if formatted_time.chars().nth(4) == Some('0') || formatted_time.chars().nth(4) == Some('5') {
-                    let response_time = start_time.elapsed().as_secs_f32();
-                    let time = Time {
-                        real_time: formatted_time,
-                        response_time: (response_time * 1000.0) as i32,
-                    };
-                    let mut counter = 0;
-                    for i in 0..list_time.len() {
-                        if time.real_time == list_time[i].real_time {
-                            counter += 1
+            // Spread the probes out a little so every target doesn't fire at the same moment
+            let jitter = target.schedule.jitter();
+            if !jitter.is_zero() {
+                let delay = rand::thread_rng().gen_range(Duration::ZERO..=jitter);
+                tokio::time::sleep(delay).await;
+            }
+
+            // Samples keep the full date and time in UTC, every view shows it in its own zone
+            let formatted_time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+            // Planned downtime from the config or switched on in the TUI, the target is still probed
+            let maintenance = target.maintenance.iter().any(|period| is_open(period, zone, Utc::now()))
+                || manual_maintenance(&summaries, &target.name);
+            tracker.set_maintenance(maintenance, Instant::now());
+
+            let probe = if target.kind == Kind::Heartbeat {
+                check_heartbeat(&target, &pings, started)
+            }
+            else {
+                probe_with_retries(&client, &target).await
+            };
+
+            // A target that rate limits us is THROTTLED instead of failing unless its policy says otherwise, it is not judged meanwhile
+            let throttled = probe.error == Some(ProbeError::Throttled) && !target.policy.throttled_is_down;
+            tracker.set_throttled(throttled, Instant::now());
+
+            // A failure while no canary answers either is our own network, the target is not judged until it is back
+            let offline = !probe.success && !throttled && target.kind != Kind::Heartbeat && !canaries.check().await;
+            let probe = if offline { probe.unknown() } else { probe };
+            tracker.set_offline(offline, Instant::now());
+
+            // Read the certificate of https targets every now and then and warn before it runs out
+            let mut cert_days = None;
+            let mut cert_error = String::new();
+            let cert_due = last_cert_check.is_none_or(|checked| checked.elapsed() >= target.cert.check_every());
+            if target.kind == Kind::Http && target.url.starts_with("https://") && cert_due {
+                last_cert_check = Some(Instant::now());
+
+                let (name, url, timeout) = (target.name.clone(), target.url.clone(), target.timeout());
+                let checked = tokio::task::spawn_blocking(move || check_cert(&name, &url, timeout))
+                    .await
+                    .unwrap_or_else(|err| Err(err.to_string()));
+                match checked {
+                    Ok(info) => {
+                        cert_days = Some(info.days_left);
+                        // Wait with the warning until the maintenance is over
+                        if let Some(threshold) = cert_watch.check(info.days_left).filter(|_| !maintenance) {
+                            notify(&notifiers, &summaries, &target.notify, cert_event(&info, threshold));
                         }
+                        update_summary(&summaries, &target.name, |summary| {
+                            summary.cert = Some(info);
+                            summary.cert_error = String::new();
+                        });
+                    }
+                    Err(err) => {
+                        cert_error = err.clone();
+                        update_summary(&summaries, &target.name, |summary| summary.cert_error = err);
                     }
-                    if counter == 0 { list_time.push(time); }
                 }
             }
-            _ => {
-                return false;
+
+            // Tell when the watched part of the page changes, a change during maintenance becomes the new normal
+            let mut hash = String::new();
+            if let Some(content) = &probe.content {
+                hash = content_hash(content);
+                if let Some(old) = last_content.as_ref().filter(|old| *old != content) {
+                    if !maintenance {
+                        notify(&notifiers, &summaries, &target.notify, content_event(&target.name, &text_diff(old, content)));
+                    }
+                }
+                if last_content.as_ref() != Some(content) {
+                    let _ = save_baseline(&target.name, content);
+                    last_content = Some(content.clone());
+                }
             }
+
+            // Tell when a target starts to redirect somewhere else, only probes that got an answer are compared
+            if probe.final_url.is_some() {
+                let chain = chain_shape(&probe.redirects);
+                if let Some(old) = last_chain.as_ref().filter(|old| **old != chain) {
+                    if !maintenance {
+                        notify(&notifiers, &summaries, &target.notify, redirect_event(&target.name, old, &chain));
+                    }
+                }
+                last_chain = Some(chain);
+            }
+
+            // Only answers outside maintenance teach the detector what is normal
+            let mut anomaly = false;
+            if let Some(detector) = detector.as_mut().filter(|_| probe.success && !maintenance) {
+                let verdict = detector.check(zone.hour_of(Utc::now()), probe.timing.total);
+                let _ = detector.save(&baseline_file);
+                anomaly = verdict.abnormal;
+                if let Some(description) = verdict.started {
+                    notify(&notifiers, &summaries, &target.notify, anomaly_event(&target.name, &description));
+                }
+            }
+            let anomaly_active = detector.as_ref().is_some_and(|detector| detector.active);
+
+            // A parent that is down is the root cause of whatever happens to this target
+            let root_cause = if offline { None } else { find_root_cause(&summaries, &target.name) };
+
+            // Alert on every change of state but keep on monitoring, nobody is notified while offline, during maintenance or while a parent is down
+            let state_before = tracker.state;
+            let change = if offline || throttled { None } else { tracker.record(probe.outcome(), Instant::now()) };
+            let alert = if offline || throttled {
+                None
+            }
+            else if maintenance || root_cause.is_some() {
+                held_from.get_or_insert(state_before);
+                None
+            }
+            else if let Some(held) = held_from.take() {
+                // Only tell about the held back time when the target didn't come back the way it was
+                Some((held, tracker.state)).filter(|(old_state, new_state)| old_state != new_state)
+            }
+            else {
+                change
+            };
+            if let Some((old_state, new_state)) = alert {
+                let reason = probe.failed_rule.clone().unwrap_or_default();
+                let dependents = dependents_of(&summaries, &target.name);
+                let event = alert_event(&target.name, old_state, new_state, &reason, &dependents, compute(&recent, since(stats_range), None));
+                notify(&notifiers, &summaries, &target.notify, event);
+            }
+
+            let state = if offline {
+                State::Unknown
+            }
+            else if throttled {
+                State::Throttled
+            }
+            else {
+                tracker.state
+            };
+
+            // Wait as long as the target asked before the next probe, or back off when it didn't say
+            let mut next_probe = String::new();
+            if probe.error == Some(ProbeError::Throttled) {
+                throttle_streak += 1;
+                let mut wait = throttle_wait(probe.retry_after, target.schedule.interval(), throttle_streak);
+                // There is no use in waiting past the end of the window
+                if let Some(left) = window.end.and_then(|end| (end - Utc::now()).to_std().ok()) {
+                    wait = wait.min(left);
+                }
+                if let Some(at) = Instant::now().checked_add(wait) {
+                    ticker.reset_at(at);
+                    next_probe = (Utc::now() + chrono::Duration::from_std(wait).unwrap_or_default()).to_rfc3339_opts(SecondsFormat::Secs, true);
+                }
+            }
+            else {
+                throttle_streak = 0;
+            }
+            let root_cause = root_cause.unwrap_or_default();
+            let time = Time {
+                target: target.name.clone(),
+                real_time: formatted_time,
+                response_time: probe.timing.total,
+                dns_time: probe.timing.dns,
+                connect_time: probe.timing.connect,
+                tls_time: probe.timing.tls,
+                ttfb: probe.timing.ttfb,
+                state,
+                maintenance,
+                error: probe.error,
+                failed_rule: probe.failed_rule.unwrap_or_default(),
+                cert_days,
+                cert_error,
+                content_hash: hash,
+                root_cause: root_cause.clone(),
+                steps: probe.step_times
+                    .iter()
+                    .map(|(name, time)| format!("{}={}", name, time))
+                    .collect::<Vec<String>>()
+                    .join("|"),
+                redirects: describe_chain(&probe.redirects),
+                final_url: probe.final_url.clone().unwrap_or_default(),
+                anomaly,
+            };
+
+            let state_times = tracker
+                .time_in_states(Instant::now())
+                .into_iter()
+                .map(|(state, duration)| StateTime {
+                    target: target.name.clone(),
+                    state,
+                    seconds: duration.as_secs(),
+                })
+                .collect();
+
+            recent.push(time.sample());
+            if let Some(oldest) = since(keep) {
+                recent.retain(|sample| sample.at >= oldest);
+            }
+            let stats: BTreeMap<String, Stats> = ranges
+                .iter()
+                .map(|range| (range.clone(), compute(&recent, parse_duration(range).and_then(since), None)))
+                .collect();
+
+            update_summary(&summaries, &target.name, |summary| {
+                summary.state = state;
+                summary.maintenance = maintenance;
+                summary.root_cause = root_cause;
+                summary.last_time = time.real_time.clone();
+                summary.last_response = time.response_time;
+                summary.state_times = state_times;
+                summary.stats = stats;
+                summary.anomaly = anomaly_active;
+                summary.next_probe = next_probe;
+            });
+
+            // Every sample goes to the file straight away so a report can read it at any time
+            let mut wtr = samples.lock().unwrap();
+            let _ = wtr.serialize(time);
+            let _ = wtr.flush();
         }
 
-        // Sleep for the specified interval before the next request
-        tokio::time::sleep(interval).await;
+        if target.schedule.window.repeat == Repeat::Once {
+            break;
+        }
+    }
+}
+
+// A function that will write how long every target spent in each state
+pub fn write_states<W: Write>(writer: W, state_times: Vec<StateTime>) -> Result<(), csv::Error> {
+    let mut wtr = Writer::from_writer(writer);
+    for state_time in state_times {
+        wtr.serialize(state_time)?;
     }
-    return true;
+    wtr.flush()?;
+    Ok(())
 }
 
-// A function that will write a .csv file
-pub fn write_csv<W: Write>(writer: W, list_time: Vec<Time>) -> Result<(), csv::Error> {
+// A function that will write the last certificate of every https target
+pub fn write_certs<W: Write>(writer: W, certs: Vec<CertInfo>) -> Result<(), csv::Error> {
     let mut wtr = Writer::from_writer(writer);
-    for time in list_time {
-        wtr.write_record(&[time.real_time, time.response_time.to_string()])?;
+    for cert in certs {
+        wtr.serialize(cert)?;
     }
     wtr.flush()?;
     Ok(())
@@ -0,0 +1,187 @@
+use regex::Regex;
+use serde_json::Value;
+
+// One step into a JSON value, a key of an object or an index of an array
+#[derive(Debug, PartialEq, Clone)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// An assertion like `$.db == "up"`, without an operator the path only has to be there
+#[derive(Clone)]
+pub struct Assertion {
+    pub text: String,
+    pub path: Vec<Step>,
+    pub check: Option<(Op, Value)>,
+}
+
+// A function that will read a path like `$.checks[0].status` or `$["db name"]`
+pub fn parse_path(text: &str) -> Result<Vec<Step>, String> {
+    let mut rest = text.strip_prefix('$').ok_or(format!("path \"{}\" must start with $", text))?;
+    let mut steps = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("empty key in path \"{}\"", text));
+            }
+            steps.push(Step::Key(after[..end].to_string()));
+            rest = &after[end..];
+        }
+        else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or(format!("missing ] in path \"{}\"", text))?;
+            let inside = after[..end].trim();
+            if let Some(key) = inside.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+                steps.push(Step::Key(key.to_string()));
+            }
+            else {
+                let index = inside.parse::<usize>().map_err(|_| format!("invalid index [{}] in path \"{}\"", inside, text))?;
+                steps.push(Step::Index(index));
+            }
+            rest = &after[end + 1..];
+        }
+        else {
+            return Err(format!("unexpected \"{}\" in path \"{}\"", rest, text));
+        }
+    }
+
+    Ok(steps)
+}
+
+// A function that will read an assertion like `$.queue_depth < 1000`, the value is written as JSON
+pub fn parse_assertion(text: &str) -> Result<Assertion, String> {
+    let regex = Regex::new(r#"^\s*(\$(?:[^\s=!<>"]|"[^"]*")*)\s*(?:(==|!=|<=|>=|<|>)\s*(.+?))?\s*$"#).unwrap();
+    let captures = regex.captures(text).ok_or(format!("invalid assertion \"{}\"", text))?;
+    let path = parse_path(&captures[1])?;
+
+    let check = match (captures.get(2), captures.get(3)) {
+        (Some(op), Some(value)) => {
+            let op = match op.as_str() {
+                "==" => Op::Eq,
+                "!=" => Op::Ne,
+                "<" => Op::Lt,
+                "<=" => Op::Le,
+                ">" => Op::Gt,
+                _ => Op::Ge,
+            };
+            let value: Value = serde_json::from_str(value.as_str())
+                .map_err(|_| format!("invalid value \"{}\" in \"{}\", strings need quotes", value.as_str(), text))?;
+            if !matches!(op, Op::Eq | Op::Ne) && !value.is_number() {
+                return Err(format!("\"{}\" can only compare numbers with <, <=, > and >=", text));
+            }
+            Some((op, value))
+        }
+        _ => None,
+    };
+
+    Ok(Assertion { text: text.trim().to_string(), path, check })
+}
+
+// A function that will follow a path into a JSON value, None when a key or index is not there
+pub fn find<'a>(value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, step| match step {
+        Step::Key(key) => value.get(key),
+        Step::Index(index) => value.get(index),
+    })
+}
+
+// Numbers are held as f64 so 1 and 1.0 are the same
+fn same(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+// A function that will check an assertion against a JSON body, gives back what was wrong
+pub fn check_assertion(assertion: &Assertion, body: &Value) -> Option<String> {
+    let found = match find(body, &assertion.path) {
+        Some(found) => found,
+        None => return Some(format!("{} failed, the path is missing", assertion.text)),
+    };
+
+    let (op, expected) = match &assertion.check {
+        Some(check) => check,
+        None => return None,
+    };
+
+    let passed = match op {
+        Op::Eq => same(found, expected),
+        Op::Ne => !same(found, expected),
+        _ => match (found.as_f64(), expected.as_f64()) {
+            (Some(found), Some(expected)) => match op {
+                Op::Lt => found < expected,
+                Op::Le => found <= expected,
+                Op::Gt => found > expected,
+                _ => found >= expected,
+            },
+            _ => false,
+        },
+    };
+
+    if passed {
+        None
+    }
+    else {
+        Some(format!("{} failed, it was {}", assertion.text, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn paths_take_quoted_keys_and_indexes() {
+        assert_eq!(parse_path(r#"$["db name"]"#).unwrap(), vec![Step::Key("db name".to_string())]);
+        assert_eq!(parse_path("$.a[0].b").unwrap(), vec![Step::Key("a".to_string()), Step::Index(0), Step::Key("b".to_string())]);
+        assert!(parse_path("$.a[x]").is_err());
+        assert!(parse_path("a.b").is_err());
+
+        let body = json!({ "db name": "up", "a": [{ "b": 7 }] });
+        assert_eq!(find(&body, &parse_path(r#"$["db name"]"#).unwrap()), Some(&json!("up")));
+        assert_eq!(find(&body, &parse_path("$.a[0].b").unwrap()), Some(&json!(7)));
+        assert_eq!(find(&body, &parse_path("$.a[1].b").unwrap()), None);
+    }
+
+    #[test]
+    fn assertions_do_not_need_spaces() {
+        let assertion = parse_assertion("$.x<5").unwrap();
+        assert_eq!(assertion.path, vec![Step::Key("x".to_string())]);
+        assert_eq!(assertion.check, Some((Op::Lt, json!(5))));
+        assert!(check_assertion(&assertion, &json!({ "x": 4 })).is_none());
+        assert!(check_assertion(&assertion, &json!({ "x": 5 })).is_some());
+
+        let assertion = parse_assertion(r#"$["db name"]=="up""#).unwrap();
+        assert!(check_assertion(&assertion, &json!({ "db name": "up" })).is_none());
+    }
+
+    #[test]
+    fn strings_need_quotes() {
+        assert!(parse_assertion("$.db == up").is_err());
+        assert!(parse_assertion(r#"$.db == "up""#).is_ok());
+    }
+
+    #[test]
+    fn only_numbers_are_ordered() {
+        assert!(parse_assertion(r#"$.db < "up""#).is_err());
+        // a number in the assertion against something else in the body fails instead of guessing
+        let assertion = parse_assertion("$.x < 5").unwrap();
+        assert!(check_assertion(&assertion, &json!({ "x": "3" })).is_some());
+        assert!(check_assertion(&assertion, &json!({ "x": null })).is_some());
+        assert!(check_assertion(&assertion, &json!({})).is_some());
+    }
+}
@@ -12,35 +12,83 @@ use helpers::*;
 mod r#struct;
 use r#struct::*;
 
+mod config;
+use config::*;
+
+mod monitor;
+
+mod probe;
+
+mod cert;
+
+mod content;
+
+mod jsonpath;
+
+mod heartbeat;
+
+mod canary;
+
+mod window;
+
+mod stats;
+
+mod anomaly;
+
+mod notify;
+use window::{describe_period, describe_window};
+
 pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
     loop {
         terminal.draw(|f| ui(f, &mut app))?;
 
-        if app.ending_connected == true { 
-            make_env(app.name, app.email);
-            let res = check_res(app.website, app.hr, app.min);
-            
-            if res == true {
-                let _ = send_email(true);
-                return Ok(()) 
-            }
-            else {
-                let _ = send_email(false);
-                return Ok(()) 
-            }
+        if app.ending_connected == true && app.monitor.is_none() { 
+            make_env(app.name.clone(), app.email.clone());
+            app.monitor = Some(Monitor::start(app.targets.clone(), app.settings.clone()));
+        }
+
+        // Every target has reached its end time, send the report and close
+        if app.monitor.as_ref().is_some_and(|monitor| monitor.is_finished()) {
+            let res = app.monitor.take().unwrap().finish();
+            let _ = send_report(&app.settings, res, None);
+            return Ok(())
+        }
+
+        // Keep on drawing the status while the targets are checked
+        if !event::poll(Duration::from_millis(500))? {
+            continue;
         }
-        else if app.ending_disconnected == true { return Ok(()) }
 
         if let Event::Key(key) = event::read()? {
             match app.input_mode {
                 InputMode::Normal => match key.code {
                     KeyCode::Char('h') => app.h_render(),
-                    KeyCode::Char('c') => {
+                    KeyCode::Char('c') if app.monitor.is_none() => {
                         app.c_render();
                         app.input_mode = InputMode::InsertingName;
                     }
                     KeyCode::Char('a') => app.a_render(),
-                    KeyCode::Char('q') => return Ok(()),
+                    // Pick a target in the status and switch its maintenance on or off
+                    KeyCode::Up if app.monitor.is_some() => {
+                        app.selected = app.selected.saturating_sub(1);
+                    }
+                    KeyCode::Down if app.monitor.is_some() && app.selected + 1 < app.targets.len() => {
+                        app.selected += 1;
+                    }
+                    KeyCode::Char('m') if app.monitor.is_some() => {
+                        app.monitor.as_ref().unwrap().toggle_maintenance(app.selected);
+                    }
+                    KeyCode::Char('s') if app.monitor.is_some() => {
+                        app.next_stats_range();
+                    }
+                    KeyCode::Char('q') => {
+                        // Stop checking and still send the report of what was checked so far
+                        if let Some(monitor) = app.monitor.take() {
+                            let res = monitor.finish();
+                            let _ = send_report(&app.settings, res, None);
+                        }
+                        return Ok(())
+                    }
                     _ => {}
                 },
                 InputMode::InsertingName if key.kind == KeyEventKind::Press => match key.code {
@@ -89,9 +137,21 @@ pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Resu
                 },
                 InputMode::InsertingWebsite if key.kind == KeyEventKind::Press => match key.code {
                     KeyCode::Enter => {
-                        app.submit_website();
-                        app.input_mode = InputMode::InsertingHour;
-                        app.hr_items.next();
+                        // An empty website means the user has finished adding targets
+                        if app.input_website.is_empty() {
+                            if !app.targets.is_empty() {
+                                app.input_mode = InputMode::Normal;
+                            }
+                        }
+                        // The daemon runs until it is stopped so there is no end time to pick
+                        else if app.settings.daemon {
+                            app.submit_website();
+                            app.submit_target();
+                        }
+                        else {
+                            app.submit_website();
+                            app.input_mode = InputMode::InsertingWindow;
+                        }
                     }
                     KeyCode::Char(to_insert) => {
                         app.enter_char_website(to_insert);
@@ -110,6 +170,37 @@ pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Resu
                     }
                     _ => {},
                 },
+                InputMode::InsertingWindow if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Enter => {
+                        // Nothing typed, only pick the time that the target stops
+                        if app.input_window.trim().is_empty() {
+                            app.window_error.clear();
+                            app.input_mode = InputMode::InsertingHour;
+                            app.hr_items.next();
+                        }
+                        // A window that doesn't make sense stays in the box with the reason above it
+                        else if app.submit_window() {
+                            app.submit_target();
+                            app.input_mode = InputMode::InsertingWebsite;
+                        }
+                    }
+                    KeyCode::Char(to_insert) => {
+                        app.enter_char_window(to_insert);
+                    }
+                    KeyCode::Backspace => {
+                        app.delete_char_window();
+                    }
+                    KeyCode::Left => {
+                        app.move_cursor_left_window();
+                    }
+                    KeyCode::Right => {
+                        app.move_cursor_right_window();
+                    }
+                    KeyCode::Esc => {
+                        app.input_mode = InputMode::Normal;
+                    }
+                    _ => {},
+                },
                 InputMode::InsertingHour if key.kind == KeyEventKind::Press => match key.code {
                     KeyCode::Enter => {
                         app.hr.push_str(&app.hr_items.items[app.hr_items.state.selected().unwrap()]);
@@ -127,7 +218,8 @@ pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Resu
                 InputMode::InsertingMinute if key.kind == KeyEventKind::Press => match key.code {
                     KeyCode::Enter => {
                         app.min.push_str(&app.min_items.items[app.min_items.state.selected().unwrap()]);
-                        app.input_mode = InputMode::Normal;
+                        app.submit_target();
+                        app.input_mode = InputMode::InsertingWebsite;
                     }
                     KeyCode::Up => {
                         app.min_items.previous()
@@ -192,6 +284,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         InputMode::InsertingName => Style::default().fg(Color::Yellow),
         InputMode::InsertingEmail => Style::default(),
         InputMode::InsertingWebsite => Style::default(),
+        InputMode::InsertingWindow => Style::default(),
         InputMode::InsertingHour => Style::default(),
         InputMode::InsertingMinute => Style::default(),
     })
@@ -203,6 +296,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         InputMode::InsertingName => Style::default(),
         InputMode::InsertingEmail => Style::default().fg(Color::Yellow),
         InputMode::InsertingWebsite => Style::default(),
+        InputMode::InsertingWindow => Style::default(),
         InputMode::InsertingHour => Style::default(),
         InputMode::InsertingMinute => Style::default(),
     })
@@ -214,11 +308,31 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         InputMode::InsertingName => Style::default(),
         InputMode::InsertingEmail => Style::default(),
         InputMode::InsertingWebsite => Style::default().fg(Color::Yellow),
+        InputMode::InsertingWindow => Style::default(),
         InputMode::InsertingHour => Style::default(),
         InputMode::InsertingMinute => Style::default(),
     })
     .block(Block::default().borders(Borders::ALL).title("Website"));
 
+    // The reason a typed window was not taken is shown where it was typed
+    let window_title = if app.window_error.is_empty() {
+        Span::raw("Window e.g. from 22:00 for 3h daily, empty to pick an end time")
+    }
+    else {
+        Span::styled(app.window_error.clone(), Style::default().fg(Color::Red))
+    };
+    let input_window = Paragraph::new(app.input_window.as_str())
+    .style(match app.input_mode {
+        InputMode::Normal => Style::default(),
+        InputMode::InsertingName => Style::default(),
+        InputMode::InsertingEmail => Style::default(),
+        InputMode::InsertingWebsite => Style::default(),
+        InputMode::InsertingWindow => Style::default().fg(Color::Yellow),
+        InputMode::InsertingHour => Style::default(),
+        InputMode::InsertingMinute => Style::default(),
+    })
+    .block(Block::default().borders(Borders::ALL).title(window_title));
+
     let hr_items: Vec<ListItem> = app
         .hr_items
         .items
@@ -236,6 +350,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             InputMode::InsertingName => Style::default(),
             InputMode::InsertingEmail => Style::default(),
             InputMode::InsertingWebsite => Style::default(),
+            InputMode::InsertingWindow => Style::default(),
             InputMode::InsertingHour => Style::default().fg(Color::Yellow),
             InputMode::InsertingMinute => Style::default(),
             }
@@ -260,6 +375,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             InputMode::InsertingName => Style::default(),
             InputMode::InsertingEmail => Style::default(),
             InputMode::InsertingWebsite => Style::default(),
+            InputMode::InsertingWindow => Style::default(),
             InputMode::InsertingHour => Style::default(),
             InputMode::InsertingMinute => Style::default().fg(Color::Yellow),
             }
@@ -284,6 +400,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                 Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Length(3),
+                Constraint::Length(3),
                 Constraint::Percentage(25),
             ]).split(main_chunks[0]);
 
@@ -299,13 +416,14 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             f.render_widget(input_name, left_chunks[0]);
             f.render_widget(input_email, left_chunks[1]);
             f.render_widget(input_website, left_chunks[2]);
+            f.render_widget(input_window, left_chunks[3]);
 
             let left_time_chunks = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([
                 Constraint::Percentage(50),
                 Constraint::Percentage(50),
-            ]).split(left_chunks[3]);
+            ]).split(left_chunks[4]);
 
             f.render_stateful_widget(hr_list, left_time_chunks[0], &mut app.hr_items.state);
             f.render_stateful_widget(min_list, left_time_chunks[1], &mut app.min_items.state);
@@ -328,8 +446,8 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                 .alignment(Alignment::Left);
             f.render_widget(website_para, mid_chunks[2]);
 
-            let mut time_para = Paragraph::new(format!("\n    Notify When -> {} : {}",app.hr.as_str(),app.min.as_str()))
-                .block(Block::default().title("Time").borders(Borders::ALL))
+            let mut time_para = Paragraph::new(render_targets(app))
+                .block(Block::default().title(format!("Time ({})", app.settings.zone.name())).borders(Borders::ALL))
                 .style(Style::default().fg(Color::White))
                 .alignment(Alignment::Left);
             f.render_widget(time_para, mid_chunks[3]);
@@ -341,9 +459,18 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             f.render_widget(messages_para, main_chunks[2]);
 
             match app.input_mode {
+                InputMode::Normal if app.ending_connected => {
+                    if let Some(monitor) = &app.monitor {
+                        messages_para = Paragraph::new(render_status(monitor, app.selected, app.settings.zone, &app.stats_range))
+                            .block(Block::default().title("Messages").borders(Borders::ALL))
+                            .style(Style::default().fg(Color::White))
+                            .alignment(Alignment::Left);
+                        f.render_widget(messages_para, main_chunks[2]);
+                    }
+                },
                 InputMode::Normal => {
-                    time_para = Paragraph::new(format!("\n    Notify When -> {} : {}",app.hr.as_str(),app.min.as_str()))
-                        .block(Block::default().title("Time").borders(Borders::ALL))
+                    time_para = Paragraph::new(render_targets(app))
+                        .block(Block::default().title(format!("Time ({})", app.settings.zone.name())).borders(Borders::ALL))
                         .style(Style::default().fg(Color::White))
                         .alignment(Alignment::Left);
 
@@ -366,7 +493,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                         delay_for(Duration::from_millis(1000)).await;
                     }
 
-                    if is_internet_connected() {
+                    if is_internet_connected(&app.settings.canaries) {
                         let message_vec_ext = vec!["Checking Network Connection...\n.\n.\n.\nConnected!", 
                         "Checking Network Connection...\n.\n.\n.\nConnected!\n.\n",
                         "Checking Network Connection...\n.\n.\n.\nConnected!\n.\nSince you have use this it will\nbe close automatically.", 
@@ -391,7 +518,8 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                         app.ending_connected = true;
                     }
                     else {
-                        message_vec.push("Checking Network Connection...\n.\n.\n.\nDisconnected!");
+                        // Don't give up, the canaries are checked all the time and the targets wait for the network to come back
+                        message_vec.push("Checking Network Connection...\n.\n.\n.\nDisconnected!\n.\nThe targets are checked anyway, a failure is\nUNKNOWN until the network is back.");
                         messages_para = Paragraph::new(message_vec[message_vec.len()-1].to_string())
                             .block(Block::default().title("Messages").borders(Borders::ALL))
                             .style(Style::default().fg(Color::White))
@@ -400,7 +528,7 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                             f.render_widget(time_para.clone(), mid_chunks[3]);
                             f.render_widget(messages_para, main_chunks[2]);
                         }).unwrap();
-                        app.ending_disconnected = true;
+                        app.ending_connected = true;
                     }
 
                     delay_for(Duration::from_millis(2000)).await;
@@ -423,6 +551,12 @@ pub async fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                         left_chunks[2].y + 1,
                     )
                 },
+                InputMode::InsertingWindow => {
+                    f.set_cursor(
+                        left_chunks[3].x + app.cursor_position_window as u16 + 1,
+                        left_chunks[3].y + 1,
+                    )
+                },
                 InputMode::InsertingHour => {},
                 InputMode::InsertingMinute => {},
             }
@@ -449,6 +583,70 @@ fn render_home<'a>() -> Paragraph<'a> {
         .alignment(Alignment::Center)
 }
 
+fn render_targets(app: &App) -> String {
+    let mut text = String::new();
+    for target in &app.targets {
+        text.push_str(format!("\n    {} every {} -> {}", target.name, target.schedule.interval, describe_window(&target.schedule)).as_str());
+        if !target.depends_on.is_empty() {
+            text.push_str(format!("\n        behind {}", target.depends_on.join(", ")).as_str());
+        }
+        for period in &target.maintenance {
+            text.push_str(format!("\n        maintenance {}", describe_period(period)).as_str());
+        }
+    }
+    for warning in &app.settings.warnings {
+        text.push_str(format!("\n\n    {}", warning).as_str());
+    }
+    if app.settings.daemon {
+        match (&app.settings.report.every, &app.settings.report.daily_at) {
+            (Some(every), _) => text.push_str(format!("\n\n    Report every {}", every).as_str()),
+            (None, Some(daily_at)) => text.push_str(format!("\n\n    Report daily at {}", daily_at).as_str()),
+            (None, None) => {}
+        }
+    }
+    text
+}
+
+fn render_status(monitor: &Monitor, selected: usize, zone: Zone, stats_range: &str) -> String {
+    let mut text = String::from("Checking... press 'q' to stop, up/down and 'm' to switch maintenance\n");
+    text.push_str(format!("Statistics over the last {}, press 's' to change\n", stats_range).as_str());
+    if !monitor.canaries.is_up() {
+        text.push_str("\nLocal network is DOWN, failures are UNKNOWN until it is back\n");
+    }
+    for (index, summary) in monitor.summaries.lock().unwrap().iter().enumerate() {
+        let marker = if index == selected { ">" } else { " " };
+        text.push_str(format!("\n{} {} : {}", marker, summary.target, summary.state).as_str());
+        if summary.maintenance || summary.manual_maintenance {
+            text.push_str(" [MAINTENANCE]");
+        }
+        if summary.anomaly {
+            text.push_str(" [LATENCY ANOMALY]");
+        }
+        if !summary.root_cause.is_empty() {
+            text.push_str(format!(" [root cause: {}]", summary.root_cause).as_str());
+        }
+        if !summary.notify_error.is_empty() {
+            text.push_str(format!(" [alert not sent: {}]", summary.notify_error).as_str());
+        }
+        if !summary.cert_error.is_empty() {
+            text.push_str(format!(" [certificate check failed: {}]", summary.cert_error).as_str());
+        }
+        if !summary.opens_at.is_empty() {
+            text.push_str(format!(" (waiting until {})", zone.show_saved(&summary.opens_at, "%Y-%m-%d %H:%M")).as_str());
+        }
+        else if !summary.last_time.is_empty() {
+            text.push_str(format!(" ({} ms at {})", summary.last_response, zone.show_saved(&summary.last_time, "%H:%M:%S")).as_str());
+        }
+        if !summary.next_probe.is_empty() {
+            text.push_str(format!(" (asked to wait, next probe at {})", zone.show_saved(&summary.next_probe, "%H:%M:%S")).as_str());
+        }
+        if let Some(stats) = summary.stats.get(stats_range).filter(|stats| stats.samples > 0) {
+            text.push_str(format!("\n    {}", describe_stats(stats)).as_str());
+        }
+    }
+    text
+}
+
 fn render_about<'a>() -> Paragraph<'a> {
     let text = vec![
         Line::from(""),
@@ -474,6 +672,18 @@ use crossterm::{
 };
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // load the preset targets before touching the terminal, their secrets come from the environment
+    dotenv::dotenv().ok();
+    let config = load_config(CONFIG_PATH)?;
+
+    // restore terminal before a panic message is printed so it is not left in raw mode
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture);
+        default_hook(info);
+    }));
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -483,7 +693,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     terminal.clear()?;
 
     // create app and run it
-    let app = App::new();
+    let mut app = App::new();
+    app.settings = config.settings();
+    app.stats_range = app.settings.stats_range.clone();
+    app.targets = config.targets;
     let res = run_app(&mut terminal, app);
 
     // restore terminal
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::config::Policy;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum State {
+    Up,
+    Degraded,
+    Down,
+    Flapping,
+    // only used for the time spent in maintenance windows, the target keeps its own state
+    Maintenance,
+    // our own network was down so nobody knows how the target was doing
+    Unknown,
+    // the target asked us to slow down, it is not judged until it answers again
+    Throttled,
+}
+
+// What one probe says about a target before the thresholds and flapping are applied
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Success,
+    // answered fine but slower than the warning latency
+    Slow,
+    Failure,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Up => write!(f, "UP"),
+            State::Degraded => write!(f, "DEGRADED"),
+            State::Down => write!(f, "DOWN"),
+            State::Flapping => write!(f, "FLAPPING"),
+            State::Maintenance => write!(f, "MAINTENANCE"),
+            State::Unknown => write!(f, "UNKNOWN"),
+            State::Throttled => write!(f, "THROTTLED"),
+        }
+    }
+}
+
+// Keeps track of one target between probes and decides what state it is in
+pub struct Tracker {
+    pub state: State,
+    // the state the target would be in if flapping was ignored
+    raw_state: State,
+    failures: u32,
+    changes: VecDeque<Instant>,
+    policy: Policy,
+    // when the target went into its current state and how long it spent in the states before
+    entered: Instant,
+    spent: Vec<(State, Duration)>,
+    // time in a maintenance window or while our network is down is counted apart so it doesn't count for or against the target
+    pub maintenance: bool,
+    pub offline: bool,
+    pub throttled: bool,
+}
+
+impl Tracker {
+    pub fn new(policy: Policy) -> Tracker {
+        Tracker {
+            state: State::Up,
+            raw_state: State::Up,
+            failures: 0,
+            changes: VecDeque::new(),
+            policy,
+            entered: Instant::now(),
+            spent: Vec::new(),
+            maintenance: false,
+            offline: false,
+            throttled: false,
+        }
+    }
+
+    // The state that the time is counted for right now
+    fn counted_state(&self) -> State {
+        if self.maintenance {
+            State::Maintenance
+        }
+        else if self.offline {
+            State::Unknown
+        }
+        else if self.throttled {
+            State::Throttled
+        }
+        else {
+            self.state
+        }
+    }
+
+    // Close the time counted so far, before what it is counted for changes
+    fn close_stretch(&mut self, now: Instant) {
+        let counted = self.counted_state();
+        Tracker::add_spent(&mut self.spent, counted, now.duration_since(self.entered));
+        self.entered = now;
+    }
+
+    // Start or stop a maintenance window, gives back true when it changed
+    pub fn set_maintenance(&mut self, maintenance: bool, now: Instant) -> bool {
+        if maintenance == self.maintenance {
+            return false;
+        }
+        self.close_stretch(now);
+        self.maintenance = maintenance;
+        true
+    }
+
+    // Our own network went down or came back, the target is not judged in the meantime
+    pub fn set_offline(&mut self, offline: bool, now: Instant) {
+        if offline != self.offline {
+            self.close_stretch(now);
+            self.offline = offline;
+        }
+    }
+
+    // The target started or stopped rate limiting us, it is not judged in the meantime
+    pub fn set_throttled(&mut self, throttled: bool, now: Instant) {
+        if throttled != self.throttled {
+            self.close_stretch(now);
+            self.throttled = throttled;
+        }
+    }
+
+    fn add_spent(spent: &mut Vec<(State, Duration)>, state: State, duration: Duration) {
+        match spent.iter_mut().find(|(spent_state, _)| *spent_state == state) {
+            Some((_, total)) => *total += duration,
+            None => spent.push((state, duration)),
+        }
+    }
+
+    // How long the target has been in each state so far
+    pub fn time_in_states(&self, now: Instant) -> Vec<(State, Duration)> {
+        let mut spent = self.spent.clone();
+        Tracker::add_spent(&mut spent, self.counted_state(), now.duration_since(self.entered));
+        spent
+    }
+
+    // Feed the result of one probe, gives back the old and new state when the state has changed
+    pub fn record(&mut self, outcome: Outcome, now: Instant) -> Option<(State, State)> {
+        if outcome == Outcome::Failure {
+            self.failures += 1;
+        }
+        else {
+            self.failures = 0;
+        }
+
+        let raw_state = if outcome == Outcome::Success {
+            State::Up
+        }
+        else if outcome == Outcome::Slow {
+            State::Degraded
+        }
+        else if self.failures >= self.policy.failures {
+            State::Down
+        }
+        else {
+            self.raw_state
+        };
+
+        if raw_state != self.raw_state {
+            self.raw_state = raw_state;
+            self.changes.push_back(now);
+        }
+
+        // Forget the changes that are older than the flap window
+        let window = self.policy.flap_window();
+        while let Some(first) = self.changes.front() {
+            if now.duration_since(*first) > window {
+                self.changes.pop_front();
+            }
+            else {
+                break;
+            }
+        }
+
+        let is_flapping = self.policy.flap_changes > 0 && self.changes.len() >= self.policy.flap_changes;
+        let new_state = if is_flapping { State::Flapping } else { self.raw_state };
+
+        if new_state != self.state {
+            let old_state = self.state;
+            self.close_stretch(now);
+            self.state = new_state;
+            return Some((old_state, new_state));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(failures: u32, flap_changes: usize) -> Policy {
+        Policy {
+            failures,
+            flap_changes,
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn down_only_after_enough_failures_in_a_row() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(policy(3, 0));
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        // a success in between starts the count again
+        assert_eq!(tracker.record(Outcome::Success, start), None);
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        assert_eq!(tracker.record(Outcome::Failure, start), Some((State::Up, State::Down)));
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        assert_eq!(tracker.record(Outcome::Success, start), Some((State::Down, State::Up)));
+    }
+
+    #[test]
+    fn flapping_while_the_changes_stay_inside_the_window() {
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let mut tracker = Tracker::new(policy(1, 4));
+        assert_eq!(tracker.record(Outcome::Failure, start), Some((State::Up, State::Down)));
+        assert_eq!(tracker.record(Outcome::Success, start + minute), Some((State::Down, State::Up)));
+        assert_eq!(tracker.record(Outcome::Failure, start + minute * 2), Some((State::Up, State::Down)));
+        assert_eq!(tracker.record(Outcome::Success, start + minute * 3), Some((State::Down, State::Flapping)));
+        // still four changes inside the 15 minutes
+        assert_eq!(tracker.record(Outcome::Success, start + minute * 10), None);
+        // the first changes fell out of the window, the target settles on its real state
+        assert_eq!(tracker.record(Outcome::Success, start + minute * 17), Some((State::Flapping, State::Up)));
+    }
+
+    #[test]
+    fn slow_answers_make_the_target_degraded() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(policy(2, 0));
+        assert_eq!(tracker.record(Outcome::Slow, start), Some((State::Up, State::Degraded)));
+        assert_eq!(tracker.record(Outcome::Slow, start), None);
+        // one failure is under the threshold, the target stays DEGRADED
+        assert_eq!(tracker.record(Outcome::Failure, start), None);
+        assert_eq!(tracker.record(Outcome::Failure, start), Some((State::Degraded, State::Down)));
+        // a slow answer ends the failures but the target is not UP yet
+        assert_eq!(tracker.record(Outcome::Slow, start), Some((State::Down, State::Degraded)));
+        assert_eq!(tracker.record(Outcome::Success, start), Some((State::Degraded, State::Up)));
+    }
+
+    #[test]
+    fn going_in_and_out_of_degraded_counts_as_flapping() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(policy(3, 4));
+        assert_eq!(tracker.record(Outcome::Slow, start), Some((State::Up, State::Degraded)));
+        assert_eq!(tracker.record(Outcome::Success, start), Some((State::Degraded, State::Up)));
+        assert_eq!(tracker.record(Outcome::Slow, start), Some((State::Up, State::Degraded)));
+        assert_eq!(tracker.record(Outcome::Success, start), Some((State::Degraded, State::Flapping)));
+    }
+
+    #[test]
+    fn no_flapping_when_it_is_turned_off() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(policy(1, 0));
+        for _ in 0..5 {
+            assert_eq!(tracker.record(Outcome::Failure, start), Some((State::Up, State::Down)));
+            assert_eq!(tracker.record(Outcome::Success, start), Some((State::Down, State::Up)));
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use reqwest::blocking::Client;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use crate::config::NotifierConfig;
+use crate::monitor::State;
+use crate::stats::Stats;
+
+// What an event is about
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    StateChange,
+    Certificate,
+    ContentChange,
+    RedirectChange,
+    Anomaly,
+    Report,
+}
+
+// Something that happened, every notifier gets the same event and shows it in its own way
+#[derive(Serialize, Clone)]
+pub struct AlertEvent {
+    pub kind: EventKind,
+    // empty for a report about every target
+    pub target: String,
+    pub old_state: Option<State>,
+    pub new_state: Option<State>,
+    // when it happened, in UTC
+    pub time: String,
+    // one line, the subject of an email
+    pub title: String,
+    // the details in plain text
+    pub text: String,
+    pub stats: Option<Stats>,
+    // the whole message as an html page, this is the report for a report
+    pub html: String,
+}
+
+// Somewhere that alerts and reports can be sent to
+pub trait Notifier: Send + Sync {
+    fn send(&self, event: &AlertEvent) -> Result<(), String>;
+}
+
+pub struct User {
+    name: String,
+    email: String,
+}
+
+// Sends the html of an event as an email through SendGrid's v3 API
+pub struct SendGrid {
+    pub url: String,
+}
+
+impl Notifier for SendGrid {
+    fn send(&self, event: &AlertEvent) -> Result<(), String> {
+        dotenv::dotenv().ok();
+
+        let read = |name: &str| env::var(name).map_err(|_| format!("{} is not set", name));
+        let api_key = read("SENDGRID_API_KEY")?;
+
+        let sender = User {
+            name: read("SENDER_NAME")?,
+            email: read("SENDER_EMAIL")?,
+        };
+
+        let recipient = User {
+            name: read("RECIPIENT_NAME")?,
+            email: read("RECIPIENT_EMAIL")?,
+        };
+
+        let body = json!(
+            {
+                "personalizations": [{
+                    "to": [{
+                        "email": recipient.email,
+                        "name": recipient.name
+                    }],
+
+                    "subject": event.title
+                }],
+                "from": {
+                    "email": sender.email,
+                    "name": sender.name
+                },
+                "subject": "",
+                "content": [
+                    {
+                        "type": "text/html",
+                        "value": event.html,
+                    },
+                ]
+            }
+        );
+
+        let response = Client::new()
+            .post(self.url.as_str())
+            .json(&body)
+            .bearer_auth(api_key)
+            .send()
+            .map_err(|err| err.to_string())?;
+        check_status(response)
+    }
+}
+
+// Posts the whole event as JSON to any url
+pub struct Webhook {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Notifier for Webhook {
+    fn send(&self, event: &AlertEvent) -> Result<(), String> {
+        let mut request = Client::new().post(self.url.as_str()).json(event);
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        check_status(request.send().map_err(|err| err.to_string())?)
+    }
+}
+
+// Posts the title and the text of an event to a Slack incoming webhook
+pub struct Slack {
+    pub url: String,
+}
+
+impl Notifier for Slack {
+    fn send(&self, event: &AlertEvent) -> Result<(), String> {
+        let body = json!({ "text": format!("*{}*\n{}", event.title, event.text) });
+        let response = Client::new()
+            .post(self.url.as_str())
+            .json(&body)
+            .send()
+            .map_err(|err| err.to_string())?;
+        check_status(response)
+    }
+}
+
+// A notifier whose secrets could not be filled in, every event sent to it fails with the reason
+struct Unusable {
+    reason: String,
+}
+
+impl Notifier for Unusable {
+    fn send(&self, _event: &AlertEvent) -> Result<(), String> {
+        Err(self.reason.clone())
+    }
+}
+
+fn check_status(response: reqwest::blocking::Response) -> Result<(), String> {
+    if response.status().is_success() {
+        Ok(())
+    }
+    else {
+        Err(format!("answered {}", response.status()))
+    }
+}
+
+// A function that will build the notifier that a config describes, with the secrets of its url and headers filled in
+pub fn build_notifier(config: &NotifierConfig) -> Box<dyn Notifier> {
+    match config.expanded() {
+        Ok(NotifierConfig::Sendgrid { url }) => Box::new(SendGrid { url }),
+        Ok(NotifierConfig::Webhook { url, headers }) => Box::new(Webhook { url, headers }),
+        Ok(NotifierConfig::Slack { url }) => Box::new(Slack { url }),
+        Err(reason) => Box::new(Unusable { reason }),
+    }
+}
+
+// Every configured notifier by its name
+pub struct Notifiers {
+    pub list: Vec<(String, Box<dyn Notifier>)>,
+}
+
+impl Notifiers {
+    pub fn new(configs: &BTreeMap<String, NotifierConfig>) -> Notifiers {
+        Notifiers {
+            list: configs.iter().map(|(name, config)| (name.clone(), build_notifier(config))).collect(),
+        }
+    }
+
+    // A function that will send an event to the notifiers a target or report routes to, every notifier when the route is empty
+    pub fn send(&self, route: &[String], event: &AlertEvent) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (name, notifier) in &self.list {
+            if !route.is_empty() && !route.contains(name) {
+                continue;
+            }
+            // One notifier that fails doesn't keep the event from the others
+            if let Err(err) = notifier.send(event) {
+                errors.push(format!("{}: {}", name, err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn event() -> AlertEvent {
+        AlertEvent {
+            kind: EventKind::StateChange,
+            target: "home".to_string(),
+            old_state: Some(State::Up),
+            new_state: Some(State::Down),
+            time: "2024-01-01T12:00:00Z".to_string(),
+            title: "home is DOWN".to_string(),
+            text: "status 500 is not in 200-299".to_string(),
+            stats: None,
+            html: "<p>home is down</p>".to_string(),
+        }
+    }
+
+    // A server that answers one request with `status` and gives back the head (in lower case) and the body of the request it got
+    fn mock(status: u16) -> (String, mpsc::Receiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Read until the head is in and then as much body as it announced
+            let (head, body_start, length) = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let head = text[..end].to_ascii_lowercase();
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .map(|value| value.trim().parse::<usize>().unwrap())
+                        .unwrap_or(0);
+                    break (head, end + 4, length);
+                }
+            };
+            while request.len() < body_start + length {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            write!(stream, "HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            let body = serde_json::from_slice(&request[body_start..body_start + length]).unwrap_or(Value::Null);
+            // A test that only looks at the answer has dropped its receiver already
+            let _ = sender.send((head, body));
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_posts_the_whole_event_with_its_headers() {
+        let (url, received) = mock(200);
+        let headers = BTreeMap::from([("X-Token".to_string(), "s3cret".to_string())]);
+        Webhook { url, headers }.send(&event()).unwrap();
+
+        let (head, body) = received.recv().unwrap();
+        assert!(head.starts_with("post /hook "));
+        assert!(head.contains("x-token: s3cret"));
+        assert!(head.contains("content-type: application/json"));
+        assert_eq!(body["kind"], "state_change");
+        assert_eq!(body["target"], "home");
+        assert_eq!(body["old_state"], "UP");
+        assert_eq!(body["new_state"], "DOWN");
+        assert_eq!(body["title"], "home is DOWN");
+        assert_eq!(body["html"], "<p>home is down</p>");
+    }
+
+    #[test]
+    fn slack_posts_the_title_and_the_text() {
+        let (url, received) = mock(200);
+        Slack { url }.send(&event()).unwrap();
+
+        let (head, body) = received.recv().unwrap();
+        assert!(head.contains("content-type: application/json"));
+        assert_eq!(body, json!({ "text": "*home is DOWN*\nstatus 500 is not in 200-299" }));
+    }
+
+    #[test]
+    fn sendgrid_sends_the_html_with_the_api_key() {
+        for (name, value) in [
+            ("SENDGRID_API_KEY", "SG.test"),
+            ("SENDER_NAME", "Monitor"),
+            ("SENDER_EMAIL", "monitor@example.com"),
+            ("RECIPIENT_NAME", "Ops"),
+            ("RECIPIENT_EMAIL", "ops@example.com"),
+        ] {
+            env::set_var(name, value);
+        }
+        let (url, received) = mock(202);
+        SendGrid { url }.send(&event()).unwrap();
+
+        let (head, body) = received.recv().unwrap();
+        assert!(head.contains("authorization: bearer sg.test"));
+        assert_eq!(body["personalizations"][0]["subject"], "home is DOWN");
+        assert_eq!(body["personalizations"][0]["to"][0]["email"], "ops@example.com");
+        assert_eq!(body["from"]["email"], "monitor@example.com");
+        assert_eq!(body["content"][0]["type"], "text/html");
+        assert_eq!(body["content"][0]["value"], "<p>home is down</p>");
+    }
+
+    #[test]
+    fn an_answer_that_is_not_2xx_is_an_error() {
+        let (url, _received) = mock(500);
+        let err = Slack { url }.send(&event()).unwrap_err();
+        assert!(err.contains("500"), "{}", err);
+
+        let (url, _received) = mock(401);
+        let err = Webhook { url, headers: BTreeMap::new() }.send(&event()).unwrap_err();
+        assert!(err.contains("401"), "{}", err);
+    }
+
+    #[test]
+    fn secrets_are_filled_in_when_the_notifier_is_built() {
+        env::set_var("NOTICHECK_TEST_HOOK_TOKEN", "t0ken");
+        let (url, received) = mock(200);
+        let config = NotifierConfig::Webhook {
+            url: url.replace("/hook", "/${NOTICHECK_TEST_HOOK_PATH}"),
+            headers: BTreeMap::from([("X-Token".to_string(), "${NOTICHECK_TEST_HOOK_TOKEN}".to_string())]),
+        };
+        // A variable that is not set fails every send and says which one it is
+        let err = build_notifier(&config).send(&event()).unwrap_err();
+        assert!(err.contains("NOTICHECK_TEST_HOOK_PATH"), "{}", err);
+
+        env::set_var("NOTICHECK_TEST_HOOK_PATH", "hook");
+        build_notifier(&config).send(&event()).unwrap();
+        let (head, _) = received.recv().unwrap();
+        assert!(head.starts_with("post /hook "));
+        assert!(head.contains("x-token: t0ken"));
+    }
+
+    #[test]
+    fn one_failing_notifier_does_not_stop_the_others() {
+        let (broken, _) = mock(503);
+        let (working, received) = mock(200);
+        let configs = BTreeMap::from([
+            ("broken".to_string(), NotifierConfig::Slack { url: broken }),
+            ("working".to_string(), NotifierConfig::Slack { url: working }),
+        ]);
+
+        let err = Notifiers::new(&configs).send(&[], &event()).unwrap_err();
+        assert!(err.starts_with("broken: "), "{}", err);
+        assert!(!err.contains("working"));
+        assert!(received.recv().is_ok());
+    }
+}
@@ -0,0 +1,795 @@
+use isahc::auth::{Authentication, Credentials};
+use isahc::config::{Configurable, RedirectPolicy};
+use isahc::cookies::CookieJar;
+use isahc::error::ErrorKind;
+use chrono::{DateTime, Utc};
+use isahc::http::header::{AUTHORIZATION, CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use isahc::http::{HeaderMap, Uri};
+use isahc::{AsyncReadResponseExt, HttpClient, Metrics, Request, ResponseExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::Instant;
+use crate::content::normalize;
+use crate::jsonpath::{check_assertion, find};
+use crate::config::{expand_env, expand_vars, Auth, Extract, Expect, Kind, Latency, RequestSpec, Target};
+use crate::monitor::Outcome;
+
+// How long each part of a probe took in milliseconds, the parts that don't apply stay 0
+#[derive(Default, Clone, Copy)]
+pub struct Timing {
+    pub dns: i32,
+    pub connect: i32,
+    pub tls: i32,
+    // from the start of the request until the first byte of the response
+    pub ttfb: i32,
+    pub total: i32,
+}
+
+fn millis(duration: Duration) -> i32 {
+    (duration.as_secs_f32() * 1000.0) as i32
+}
+
+impl Timing {
+    fn from_metrics(metrics: &Metrics, total: Duration) -> Timing {
+        Timing {
+            dns: millis(metrics.name_lookup_time()),
+            connect: millis(metrics.connect_time()),
+            tls: millis(metrics.secure_connect_time()),
+            ttfb: millis(metrics.transfer_start_time()),
+            total: millis(total),
+        }
+    }
+
+    // A flow takes as long as all of its steps together
+    fn add(&mut self, other: &Timing) {
+        self.dns += other.dns;
+        self.connect += other.connect;
+        self.tls += other.tls;
+        self.ttfb += other.ttfb;
+        self.total += other.total;
+    }
+}
+
+// Why a probe failed, saved with every sample
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeError {
+    Timeout,
+    Dns,
+    ConnectionRefused,
+    Tls,
+    HttpStatus,
+    Assertion,
+    // no canary answered either so the failure says nothing about the target
+    LocalNetwork,
+    // 429, or 503 with a Retry-After, the target asked us to slow down
+    Throttled,
+    Other,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::Timeout => write!(f, "timeout"),
+            ProbeError::Dns => write!(f, "dns"),
+            ProbeError::ConnectionRefused => write!(f, "connection refused"),
+            ProbeError::Tls => write!(f, "tls"),
+            ProbeError::HttpStatus => write!(f, "http status"),
+            ProbeError::Assertion => write!(f, "assertion"),
+            ProbeError::LocalNetwork => write!(f, "unknown, local network"),
+            ProbeError::Throttled => write!(f, "throttled"),
+            ProbeError::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl From<&isahc::Error> for ProbeError {
+    fn from(err: &isahc::Error) -> ProbeError {
+        match err.kind() {
+            ErrorKind::Timeout => ProbeError::Timeout,
+            ErrorKind::NameResolution => ProbeError::Dns,
+            ErrorKind::ConnectionFailed => ProbeError::ConnectionRefused,
+            ErrorKind::BadServerCertificate | ErrorKind::BadClientCertificate | ErrorKind::TlsEngine => ProbeError::Tls,
+            _ => ProbeError::Other,
+        }
+    }
+}
+
+impl From<&io::Error> for ProbeError {
+    fn from(err: &io::Error) -> ProbeError {
+        match err.kind() {
+            io::ErrorKind::TimedOut => ProbeError::Timeout,
+            io::ErrorKind::ConnectionRefused => ProbeError::ConnectionRefused,
+            _ => ProbeError::Other,
+        }
+    }
+}
+
+// One redirect that a probe followed
+#[derive(Clone)]
+pub struct Hop {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+    pub time: i32,
+}
+
+// A function that will write a redirect chain as "301 http://a -> https://a/ (12 ms)" hops
+pub fn describe_chain(hops: &[Hop]) -> String {
+    hops.iter()
+        .map(|hop| format!("{} {} -> {} ({} ms)", hop.status, hop.url, hop.location, hop.time))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// A function that will write where a redirect chain goes as "301 https://a/login" hops, the query strings are left out so a new session token or cache buster is not a change
+pub fn chain_shape(hops: &[Hop]) -> String {
+    hops.iter()
+        .map(|hop| match hop.location.parse::<Uri>() {
+            Ok(uri) => format!("{} {}://{}{}", hop.status, uri.scheme_str().unwrap_or_default(), uri.authority().map(|authority| authority.as_str()).unwrap_or_default(), uri.path()),
+            Err(_) => format!("{} {}", hop.status, hop.location),
+        })
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// What came back from probing a target once
+pub struct Probe {
+    pub success: bool,
+    // slower than the warning latency of the target
+    pub slow: bool,
+    pub timing: Timing,
+    pub error: Option<ProbeError>,
+    // the rule that the response broke, None when the probe was a success
+    pub failed_rule: Option<String>,
+    // the watched part of the page, only on successful probes of targets that watch their content
+    pub content: Option<String>,
+    // how long each step of a flow took, empty for a single request
+    pub step_times: Vec<(String, i32)>,
+    // the redirects that were followed, in order
+    pub redirects: Vec<Hop>,
+    // the url that answered in the end, None when no answer came back
+    pub final_url: Option<String>,
+    // how long the target asked us to wait before the next probe
+    pub retry_after: Option<Duration>,
+}
+
+impl Probe {
+    pub fn passed(timing: Timing) -> Probe {
+        Probe {
+            success: true,
+            slow: false,
+            timing,
+            error: None,
+            failed_rule: None,
+            content: None,
+            step_times: Vec::new(),
+            redirects: Vec::new(),
+            final_url: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn failed(timing: Timing, error: ProbeError, rule: String) -> Probe {
+        Probe {
+            success: false,
+            slow: false,
+            timing,
+            error: Some(error),
+            failed_rule: Some(rule),
+            content: None,
+            step_times: Vec::new(),
+            redirects: Vec::new(),
+            final_url: None,
+            retry_after: None,
+        }
+    }
+
+    // A function that will hold a successful probe against the latency thresholds of its target
+    fn check_latency(self, latency: &Latency) -> Probe {
+        if !self.success {
+            return self;
+        }
+
+        if let Some(critical) = latency.critical_ms {
+            if self.timing.total >= critical {
+                let rule = format!("latency {} ms is over the critical {} ms", self.timing.total, critical);
+                return Probe { success: false, error: Some(ProbeError::Assertion), failed_rule: Some(rule), ..self };
+            }
+        }
+
+        let slow = latency.warning_ms.is_some_and(|warning| self.timing.total >= warning);
+        Probe { slow, ..self }
+    }
+
+    // A failure while our own network is down
+    pub fn unknown(self) -> Probe {
+        let rule = format!("unknown, local network ({})", self.failed_rule.as_deref().unwrap_or_default());
+        Probe { success: false, error: Some(ProbeError::LocalNetwork), failed_rule: Some(rule), ..self }
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        if !self.success {
+            Outcome::Failure
+        }
+        else if self.slow {
+            Outcome::Slow
+        }
+        else {
+            Outcome::Success
+        }
+    }
+}
+
+// A function that will build the http client for a target, redirects are followed by hand so every hop is recorded
+pub fn build_client(target: &Target) -> HttpClient {
+    HttpClient::builder()
+        .redirect_policy(RedirectPolicy::None)
+        .timeout(target.timeout())
+        .metrics(true)
+        .build()
+        .unwrap()
+}
+
+// The cookies and the values taken out of the responses that the steps of a flow share
+#[derive(Default)]
+pub struct Session {
+    pub cookies: CookieJar,
+    pub vars: BTreeMap<String, String>,
+}
+
+// A function that will fill in every string of a json body, a value with quotes or backslashes stays one string when the body is written out
+fn fill_json(value: &Value, fill: &dyn Fn(&str) -> Result<String, String>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(text) => Value::String(fill(text)?),
+        Value::Array(items) => Value::Array(items.iter().map(|item| fill_json(item, fill)).collect::<Result<_, _>>()?),
+        Value::Object(fields) => {
+            let mut filled = serde_json::Map::new();
+            for (name, field) in fields {
+                filled.insert(fill(name)?, fill_json(field, fill)?);
+            }
+            Value::Object(filled)
+        }
+        other => other.clone(),
+    })
+}
+
+// A function that will build a request, filling the secrets in from the environment and the values of a flow from its session
+pub fn build_request(url: &str, spec: &RequestSpec, session: Option<&Session>) -> Result<Request<Vec<u8>>, String> {
+    let fill = |text: &str| -> Result<String, String> {
+        let text = expand_env(text)?;
+        match session {
+            Some(session) => expand_vars(&text, &session.vars),
+            None => Ok(text),
+        }
+    };
+
+    let mut builder = Request::builder()
+        .method(spec.method.as_str())
+        .uri(fill(url)?);
+
+    if let Some(session) = session {
+        builder = builder.cookie_jar(session.cookies.clone());
+    }
+
+    for (name, value) in &spec.headers {
+        builder = builder.header(name.as_str(), fill(value)?);
+    }
+
+    match &spec.auth {
+        Some(Auth::Basic { username, password }) => {
+            builder = builder
+                .authentication(Authentication::basic())
+                .credentials(Credentials::new(fill(username)?, fill(password)?));
+        }
+        Some(Auth::Bearer { token }) => {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {}", fill(token)?));
+        }
+        None => {}
+    }
+
+    let body = if let Some(json) = &spec.json {
+        builder = builder.header(CONTENT_TYPE, "application/json");
+        fill_json(json, &fill)?.to_string().into_bytes()
+    }
+    else if let Some(body) = &spec.body {
+        fill(body)?.into_bytes()
+    }
+    else {
+        Vec::new()
+    };
+
+    builder.body(body).map_err(|err| err.to_string())
+}
+
+// A function that will read a Retry-After header, either seconds or an http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some((at.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+// A function that will check the url that answered in the end against the final_url pattern
+fn check_final_url(expect: &Expect, uri: &Uri) -> Option<String> {
+    let regex = expect.final_url_regex.as_ref()?;
+    let url = uri.to_string();
+    if regex.is_match(&url) {
+        None
+    }
+    else {
+        Some(format!("final url {} does not match /{}/", url, regex.as_str()))
+    }
+}
+
+// A function that will check a body against the body rules, gives back the rule that failed
+fn check_body(expect: &Expect, body: &str) -> Option<String> {
+    if let Some(text) = &expect.body_contains {
+        if body.contains(text.as_str()) == expect.body_absent {
+            if expect.body_absent {
+                return Some(format!("body contains \"{}\"", text));
+            }
+            return Some(format!("body does not contain \"{}\"", text));
+        }
+    }
+
+    if let Some(regex) = &expect.body_regex_compiled {
+        if regex.is_match(body) == expect.body_absent {
+            if expect.body_absent {
+                return Some(format!("body matches /{}/", regex.as_str()));
+            }
+            return Some(format!("body does not match /{}/", regex.as_str()));
+        }
+    }
+
+    None
+}
+
+// A function that will check a JSON body against the json assertions, gives back every assertion that failed
+fn check_json(expect: &Expect, body: &str) -> Option<String> {
+    if expect.assertions.is_empty() {
+        return None;
+    }
+
+    let value: Value = match serde_json::from_str(body) {
+        Ok(value) => value,
+        Err(err) => return Some(format!("body is not JSON, {}", err)),
+    };
+
+    let failed: Vec<String> = expect.assertions
+        .iter()
+        .filter_map(|assertion| check_assertion(assertion, &value))
+        .collect();
+    if failed.is_empty() {
+        None
+    }
+    else {
+        Some(failed.join("; "))
+    }
+}
+
+// A function that will turn the Location of a redirect into a full url, it can be relative to the url that redirected
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    if has_scheme(location) {
+        return location.parse().ok();
+    }
+
+    let scheme = base.scheme_str()?;
+    let authority = base.authority()?;
+    let url = if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    }
+    else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    }
+    // only a new query string, the path stays the same
+    else if location.starts_with('?') {
+        format!("{}://{}{}{}", scheme, authority, base.path(), location)
+    }
+    else {
+        let path = base.path();
+        let folder = &path[..path.rfind('/').map(|index| index + 1).unwrap_or(0)];
+        format!("{}://{}{}{}", scheme, authority, folder, location)
+    };
+    url.parse().ok()
+}
+
+// Whether a Location starts with a scheme like "https:", a "://" further on can be in the query of a relative one
+fn has_scheme(location: &str) -> bool {
+    match location.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|first: char| first.is_ascii_alphabetic())
+                && scheme.chars().all(|letter| letter.is_ascii_alphanumeric() || "+.-".contains(letter))
+        }
+        None => false,
+    }
+}
+
+// The scheme, host and port of a url, the port is filled in when the url leaves it out
+fn origin(uri: &Uri) -> (Option<&str>, Option<&str>, Option<u16>) {
+    let port = uri.port_u16().or(match uri.scheme_str() {
+        Some("https") => Some(443),
+        Some("http") => Some(80),
+        _ => None,
+    });
+    (uri.scheme_str(), uri.host(), port)
+}
+
+// A function that will build the request for the next hop of a redirect chain
+fn next_hop(url: &str, spec: &RequestSpec, session: Option<&Session>, first: &Uri, location: &Uri, status: u16) -> Result<Request<Vec<u8>>, String> {
+    let mut spec = spec.clone();
+
+    // Credentials and the headers that may carry secrets or cookies only go to the scheme, host and port they were written for, never over plain http after https
+    if origin(location) != origin(first) {
+        spec.auth = None;
+        spec.headers.clear();
+    }
+
+    // 301, 302 and 303 turn into a GET without a body like they do in a browser
+    if matches!(status, 301..=303) && spec.method != "HEAD" {
+        spec.method = "GET".to_string();
+        spec.body = None;
+        spec.json = None;
+    }
+
+    let mut request = build_request(url, &spec, session)?;
+    *request.uri_mut() = location.clone();
+    Ok(request)
+}
+
+// What one request brought back, a flow takes its values out of the body, the headers and the cookies
+struct Exchange {
+    probe: Probe,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl Exchange {
+    fn failed(probe: Probe) -> Exchange {
+        Exchange {
+            probe,
+            uri: Uri::default(),
+            headers: HeaderMap::new(),
+            body: String::new(),
+        }
+    }
+}
+
+// A function that will send one request, follow its redirects and check the final response against its rules
+async fn exchange(client: &HttpClient, url: &str, spec: &RequestSpec, expect: &Expect, session: Option<&Session>) -> Exchange {
+    let start_time = Instant::now();
+    let mut timing = Timing::default();
+    let mut redirects: Vec<Hop> = Vec::new();
+
+    let mut request = match build_request(url, spec, session) {
+        Ok(request) => request,
+        Err(err) => return Exchange::failed(Probe::failed(timing, ProbeError::Other, err)),
+    };
+    let first = request.uri().clone();
+
+    // Every hop of the chain is its own request, the timings of all of them add up
+    let (mut response, body, uri) = loop {
+        let uri = request.uri().clone();
+        let hop_time = Instant::now();
+
+        // Send the request and read the whole body so the total time is complete
+        let mut response = match client.send_async(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                timing.total = millis(start_time.elapsed());
+                let probe = Probe::failed(timing, ProbeError::from(&err), err.to_string());
+                return Exchange::failed(Probe { redirects, ..probe });
+            }
+        };
+        let body = response.text().await;
+        let hop_total = hop_time.elapsed();
+        match response.metrics() {
+            Some(metrics) => timing.add(&Timing::from_metrics(metrics, hop_total)),
+            None => timing.add(&Timing { total: millis(hop_total), ..Timing::default() }),
+        }
+
+        let status = response.status();
+        let location = response.headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|location| resolve(&uri, location));
+        let location = match location {
+            Some(location) if status.is_redirection() && expect.follow_redirects => location,
+            _ => break (response, body, uri),
+        };
+
+        redirects.push(Hop {
+            url: uri.to_string(),
+            status: status.as_u16(),
+            location: location.to_string(),
+            time: millis(hop_total),
+        });
+        if redirects.len() > expect.max_redirects {
+            timing.total = millis(start_time.elapsed());
+            let rule = format!("more than {} redirects", expect.max_redirects);
+            let probe = Probe::failed(timing, ProbeError::Assertion, rule);
+            return Exchange::failed(Probe { redirects, ..probe });
+        }
+
+        request = match next_hop(url, spec, session, &first, &location, status.as_u16()) {
+            Ok(request) => request,
+            Err(err) => {
+                timing.total = millis(start_time.elapsed());
+                return Exchange::failed(Probe { redirects, ..Probe::failed(timing, ProbeError::Other, err) });
+            }
+        };
+    };
+    timing.total = millis(start_time.elapsed());
+    let final_url = Some(uri.to_string());
+
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => {
+            let rule = format!("reading body failed: {}", err);
+            let probe = Probe::failed(timing, ProbeError::from(&err), rule);
+            return Exchange::failed(Probe { redirects, final_url, ..probe });
+        }
+    };
+
+    let status = response.status().as_u16();
+    let retry_after = response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let probe = if !expect.accepts_status(status) && (status == 429 || (status == 503 && retry_after.is_some())) {
+        let rule = match retry_after {
+            Some(wait) => format!("status {}, retry after {}s", status, wait.as_secs()),
+            None => format!("status {}", status),
+        };
+        Probe { retry_after, ..Probe::failed(timing, ProbeError::Throttled, rule) }
+    }
+    else if !expect.accepts_status(status) {
+        let rule = format!("status {} is not in {}", status, expect.status.join(", "));
+        Probe::failed(timing, ProbeError::HttpStatus, rule)
+    }
+    else if let Some(rule) = check_final_url(expect, &uri).or_else(|| check_body(expect, &body)).or_else(|| check_json(expect, &body)) {
+        Probe::failed(timing, ProbeError::Assertion, rule)
+    }
+    else {
+        Probe::passed(timing)
+    };
+
+    Exchange {
+        probe: Probe { redirects, final_url, ..probe },
+        uri,
+        headers: std::mem::take(response.headers_mut()),
+        body,
+    }
+}
+
+// A function that will probe a target once and check the response against its rules
+pub async fn probe_http(client: &HttpClient, target: &Target) -> Probe {
+    let exchange = exchange(client, &target.url, &target.request, &target.expect, None).await;
+    if !exchange.probe.success {
+        return exchange.probe;
+    }
+
+    let content = target.content.as_ref().map(|rule| normalize(rule, &exchange.body));
+    Probe { content, ..exchange.probe }
+}
+
+// A function that will take a value out of a response with a rule that was read when the config was loaded
+fn extract(rule: &Extract, exchange: &Exchange, session: &Session) -> Option<String> {
+    match rule {
+        Extract::Cookie(name) => session.cookies.get_by_name(&exchange.uri, name).map(|cookie| cookie.value().to_string()),
+        Extract::Header(name) => exchange.headers.get(name)?.to_str().ok().map(|value| value.to_string()),
+        Extract::Regex(regex) => regex.captures(&exchange.body)?.get(1).map(|group| group.as_str().to_string()),
+        Extract::Json(path) => {
+            let value: Value = serde_json::from_str(&exchange.body).ok()?;
+            match find(&value, path)? {
+                Value::String(text) => Some(text.clone()),
+                Value::Null => None,
+                value => Some(value.to_string()),
+            }
+        }
+    }
+}
+
+// A function that will run the steps of a flow in order, the first step that fails is the one that broke the flow
+pub async fn probe_flow(client: &HttpClient, target: &Target) -> Probe {
+    let mut session = Session::default();
+    let mut timing = Timing::default();
+    let mut step_times = Vec::new();
+    let mut redirects = Vec::new();
+    let mut final_url = None;
+    let mut body = String::new();
+
+    for step in &target.steps {
+        let exchange = exchange(client, &step.url, &step.request, &step.expect, Some(&session)).await;
+        timing.add(&exchange.probe.timing);
+        step_times.push((step.name.clone(), exchange.probe.timing.total));
+        redirects.extend(exchange.probe.redirects.iter().cloned());
+        final_url = exchange.probe.final_url.clone();
+
+        if !exchange.probe.success {
+            let error = exchange.probe.error.unwrap_or(ProbeError::Other);
+            let rule = format!("step {}: {}", step.name, exchange.probe.failed_rule.unwrap_or_default());
+            let retry_after = exchange.probe.retry_after;
+            return Probe { step_times, redirects, final_url, retry_after, ..Probe::failed(timing, error, rule) };
+        }
+
+        for (name, rule) in &step.extracts {
+            match extract(rule, &exchange, &session) {
+                Some(value) => {
+                    session.vars.insert(name.clone(), value);
+                }
+                None => {
+                    let rule = format!("step {}: nothing found for {} at {}", step.name, name, step.extract[name]);
+                    return Probe { step_times, redirects, final_url, ..Probe::failed(timing, ProbeError::Assertion, rule) };
+                }
+            }
+        }
+        body = exchange.body;
+    }
+
+    // The content of a flow is the page its last step ends on
+    let content = target.content.as_ref().map(|rule| normalize(rule, &body));
+    Probe { content, step_times, redirects, final_url, ..Probe::passed(timing) }
+}
+
+// A function that will check that a tcp port accepts a connection, the response time is the connect time
+pub async fn probe_tcp(target: &Target) -> Probe {
+    let start_time = Instant::now();
+    let mut timing = Timing::default();
+
+    let connect = async {
+        let mut addresses = match lookup_host(target.url.as_str()).await {
+            Ok(addresses) => addresses,
+            Err(err) => return Err((ProbeError::Dns, format!("lookup failed: {}", err))),
+        };
+        timing.dns = millis(start_time.elapsed());
+
+        let address = match addresses.next() {
+            Some(address) => address,
+            None => return Err((ProbeError::Dns, format!("no address found for {}", target.url))),
+        };
+
+        let connect_start = Instant::now();
+        let result = TcpStream::connect(address).await;
+        timing.connect = millis(connect_start.elapsed());
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => Err((ProbeError::from(&err), format!("connect failed: {}", err))),
+        }
+    };
+
+    let result = match tokio::time::timeout(target.timeout(), connect).await {
+        Ok(result) => result,
+        Err(_) => Err((ProbeError::Timeout, format!("no connection after {}", target.timeout))),
+    };
+    timing.total = millis(start_time.elapsed());
+
+    match result {
+        Ok(()) => Probe::passed(timing),
+        Err((error, rule)) => Probe::failed(timing, error, rule),
+    }
+}
+
+// A function that will probe a target once with the probe that fits its kind
+pub async fn probe_once(client: &HttpClient, target: &Target) -> Probe {
+    let probe = match target.kind {
+        Kind::Http if !target.steps.is_empty() => probe_flow(client, target).await,
+        Kind::Http => probe_http(client, target).await,
+        Kind::Tcp => probe_tcp(target).await,
+        // heartbeats are pinged by their job, check_heartbeat looks at them
+        Kind::Heartbeat => Probe::failed(Timing::default(), ProbeError::Other, "a heartbeat is not probed".to_string()),
+    };
+    probe.check_latency(&target.latency)
+}
+
+// A function that will probe a target, retrying with a backoff before it counts as a failure
+pub async fn probe_with_retries(client: &HttpClient, target: &Target) -> Probe {
+    let mut backoff = target.policy.backoff();
+    let mut attempt = 0;
+
+    loop {
+        let probe = probe_once(client, target).await;
+
+        // A target that asked us to slow down is not asked again straight away
+        if probe.success || probe.error == Some(ProbeError::Throttled) || attempt >= target.policy.retries {
+            return probe;
+        }
+
+        attempt += 1;
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn json_body_fills_in_strings_only() {
+        env::set_var("NOTICHECK_TEST_SECRET", "pa\"ss\\word");
+        let spec: RequestSpec = serde_json::from_str(r#"{
+            "method": "POST",
+            "json": { "password": "${NOTICHECK_TEST_SECRET}", "tags": ["x-${NOTICHECK_TEST_SECRET}"], "count": 2 }
+        }"#).unwrap();
+
+        let request = build_request("http://localhost/login", &spec, None).unwrap();
+        let body: Value = serde_json::from_slice(request.body()).unwrap();
+        assert_eq!(body["password"], "pa\"ss\\word");
+        assert_eq!(body["tags"][0], "x-pa\"ss\\word");
+        assert_eq!(body["count"], 2);
+    }
+
+    #[test]
+    fn json_body_fills_in_flow_values_per_string() {
+        let spec: RequestSpec = serde_json::from_str(r#"{ "method": "POST", "json": { "token": "{{token}}", "note": "{{token}} again" } }"#).unwrap();
+        let mut session = Session::default();
+        session.vars.insert("token".to_string(), "a\"b\\c".to_string());
+
+        let request = build_request("http://localhost/next", &spec, Some(&session)).unwrap();
+        let body: Value = serde_json::from_slice(request.body()).unwrap();
+        assert_eq!(body["token"], "a\"b\\c");
+        assert_eq!(body["note"], "a\"b\\c again");
+    }
+
+    #[test]
+    fn credentials_stay_on_their_origin() {
+        let spec: RequestSpec = serde_json::from_str(r#"{ "method": "GET", "headers": { "X-Api-Key": "k3y", "Cookie": "session=1" }, "auth": { "bearer": { "token": "t0k" } } }"#).unwrap();
+        let first: Uri = "https://example.com/start".parse().unwrap();
+        let hop = |location: &str| {
+            let request = next_hop("https://example.com/start", &spec, None, &first, &location.parse().unwrap(), 302).unwrap();
+            let headers = request.headers();
+            [AUTHORIZATION.as_str(), "x-api-key", "cookie"].iter().filter(|name| headers.contains_key(**name)).count()
+        };
+
+        assert_eq!(hop("https://example.com:443/next"), 3);
+        assert_eq!(hop("http://example.com/next"), 0);
+        assert_eq!(hop("https://example.com:8443/next"), 0);
+        assert_eq!(hop("https://other.example.com/next"), 0);
+    }
+
+    #[test]
+    fn a_url_in_the_query_keeps_a_location_relative() {
+        let base: Uri = "https://example.com/a/b".parse().unwrap();
+        let resolved = resolve(&base, "/login?next=https://other.example.com/x").unwrap();
+        assert_eq!(resolved.to_string(), "https://example.com/login?next=https://other.example.com/x");
+        let resolved = resolve(&base, "c?next=http://other.example.com").unwrap();
+        assert_eq!(resolved.to_string(), "https://example.com/a/c?next=http://other.example.com");
+        assert_eq!(resolve(&base, "http://other.example.com/x").unwrap().to_string(), "http://other.example.com/x");
+        assert_eq!(resolve(&base, "//other.example.com/x").unwrap().to_string(), "https://other.example.com/x");
+    }
+
+    #[test]
+    fn a_query_only_location_keeps_the_path() {
+        let base: Uri = "https://example.com/a/b?old=1".parse().unwrap();
+        assert_eq!(resolve(&base, "?x=1").unwrap().to_string(), "https://example.com/a/b?x=1");
+    }
+
+    #[tokio::test]
+    async fn tcp_probe_against_a_local_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let target = Target::from_input(&format!("local=tcp://{}", address), "23", "59");
+
+        let probe = probe_tcp(&target).await;
+        assert!(probe.success, "{:?}", probe.failed_rule);
+        assert_eq!(probe.error, None);
+        assert!(probe.timing.connect >= 0 && probe.timing.connect <= probe.timing.total);
+
+        // Nothing listens on the port once the listener is gone
+        drop(listener);
+        let probe = probe_tcp(&target).await;
+        assert!(!probe.success);
+        assert_eq!(probe.error, Some(ProbeError::ConnectionRefused));
+        assert!(probe.failed_rule.unwrap().starts_with("connect failed"));
+    }
+}
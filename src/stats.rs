@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::monitor::State;
+
+// One saved probe of a target as the statistics need it
+#[derive(Clone)]
+pub struct Sample {
+    pub at: DateTime<Utc>,
+    pub response_time: i32,
+    pub state: State,
+    pub maintenance: bool,
+    // the probe itself failed, its time says nothing about the latency
+    pub failed: bool,
+}
+
+// The numbers of one target over a time range, latencies are in milliseconds
+#[derive(Clone, Default, Serialize)]
+pub struct Stats {
+    // the samples that counted, samples in maintenance, while our network was down or while throttled are left out
+    pub samples: usize,
+    // None when no sample counted
+    pub uptime: Option<f64>,
+    pub mean: i32,
+    pub min: i32,
+    pub max: i32,
+    pub p50: i32,
+    pub p90: i32,
+    pub p95: i32,
+    pub p99: i32,
+    pub incidents: usize,
+    // the mean time from going DOWN to being back, None when no incident is over yet
+    pub mttr: Option<u64>,
+}
+
+fn is_down(state: State) -> bool {
+    matches!(state, State::Down | State::Flapping)
+}
+
+// A function that will give the latency that `percent` of the sorted latencies are at or under
+pub fn percentile(sorted: &[i32], percent: f64) -> i32 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// A function that will compute the statistics of the samples of one target between `from` and `to`, an open end takes everything on that side
+pub fn compute(samples: &[Sample], from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Stats {
+    let counted: Vec<&Sample> = samples
+        .iter()
+        .filter(|sample| from.is_none_or(|from| sample.at >= from) && to.is_none_or(|to| sample.at <= to))
+        .filter(|sample| !sample.maintenance && !matches!(sample.state, State::Unknown | State::Throttled))
+        .collect();
+    if counted.is_empty() {
+        return Stats::default();
+    }
+
+    let up = counted.iter().filter(|sample| !is_down(sample.state)).count();
+    let uptime = Some(up as f64 * 100.0 / counted.len() as f64);
+
+    let mut latencies: Vec<i32> = counted
+        .iter()
+        .filter(|sample| !sample.failed)
+        .map(|sample| sample.response_time)
+        .collect();
+    latencies.sort();
+    let mean = if latencies.is_empty() {
+        0
+    }
+    else {
+        (latencies.iter().map(|&latency| latency as i64).sum::<i64>() / latencies.len() as i64) as i32
+    };
+
+    // An incident starts with the first DOWN sample and is over with the first sample that is not
+    let mut incidents = 0;
+    let mut recoveries: Vec<i64> = Vec::new();
+    let mut down_since: Option<DateTime<Utc>> = None;
+    for sample in &counted {
+        match (down_since, is_down(sample.state)) {
+            (None, true) => {
+                incidents += 1;
+                down_since = Some(sample.at);
+            }
+            (Some(since), false) => {
+                recoveries.push((sample.at - since).num_seconds());
+                down_since = None;
+            }
+            _ => {}
+        }
+    }
+    let mttr = if recoveries.is_empty() {
+        None
+    }
+    else {
+        Some((recoveries.iter().sum::<i64>() / recoveries.len() as i64) as u64)
+    };
+
+    Stats {
+        samples: counted.len(),
+        uptime,
+        mean,
+        min: latencies.first().copied().unwrap_or_default(),
+        max: latencies.last().copied().unwrap_or_default(),
+        p50: percentile(&latencies, 50.0),
+        p90: percentile(&latencies, 90.0),
+        p95: percentile(&latencies, 95.0),
+        p99: percentile(&latencies, 99.0),
+        incidents,
+        mttr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(minute: u32, state: State, response_time: i32) -> Sample {
+        Sample {
+            at: Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, 0).unwrap(),
+            response_time,
+            state,
+            maintenance: false,
+            failed: false,
+        }
+    }
+
+    #[test]
+    fn percentile_takes_the_nearest_rank() {
+        let sorted: Vec<i32> = (1..=10).map(|latency| latency * 10).collect();
+        assert_eq!(percentile(&sorted, 50.0), 50);
+        assert_eq!(percentile(&sorted, 90.0), 90);
+        assert_eq!(percentile(&sorted, 91.0), 100);
+        assert_eq!(percentile(&sorted, 99.0), 100);
+        assert_eq!(percentile(&sorted, 100.0), 100);
+        assert_eq!(percentile(&sorted, 0.0), 10);
+        assert_eq!(percentile(&[7], 99.0), 7);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn an_open_incident_is_counted_but_has_no_recovery_yet() {
+        let samples = vec![
+            sample(0, State::Up, 100),
+            sample(1, State::Down, 100),
+            sample(3, State::Up, 100),
+            sample(4, State::Down, 100),
+            sample(5, State::Flapping, 100),
+        ];
+        let stats = compute(&samples, None, None);
+        assert_eq!(stats.incidents, 2);
+        // only the first incident is over, it took two minutes
+        assert_eq!(stats.mttr, Some(120));
+        assert_eq!(stats.uptime, Some(40.0));
+
+        let stats = compute(&samples[3..], None, None);
+        assert_eq!(stats.incidents, 1);
+        assert_eq!(stats.mttr, None);
+    }
+
+    #[test]
+    fn maintenance_unknown_and_throttled_samples_are_left_out() {
+        let mut in_maintenance = sample(1, State::Down, 900);
+        in_maintenance.maintenance = true;
+        let mut failed = sample(5, State::Up, 5000);
+        failed.failed = true;
+        let samples = vec![
+            sample(0, State::Up, 100),
+            in_maintenance,
+            sample(2, State::Unknown, 800),
+            sample(3, State::Throttled, 700),
+            sample(4, State::Up, 300),
+            failed,
+        ];
+
+        let stats = compute(&samples, None, None);
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.uptime, Some(100.0));
+        assert_eq!(stats.incidents, 0);
+        // a failed probe counts for the uptime but not for the latency
+        assert_eq!((stats.min, stats.max, stats.mean), (100, 300, 200));
+    }
+
+    #[test]
+    fn only_the_samples_in_range_count() {
+        let samples = vec![sample(0, State::Down, 100), sample(10, State::Up, 200), sample(20, State::Up, 300)];
+        let stats = compute(&samples, Some(samples[1].at), None);
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.incidents, 0);
+        let stats = compute(&samples, None, Some(samples[1].at));
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.incidents, 1);
+        assert_eq!(compute(&[], None, None).uptime, None);
+    }
+}
@@ -1,10 +1,14 @@
 use ratatui::widgets::*;
+use crate::config::{Period, Repeat, Settings, Target, STATS_RANGES};
+use crate::helpers::Monitor;
+use crate::window::{check_run_period, parse_window};
 
 pub enum InputMode {
     Normal,
     InsertingName,
     InsertingEmail,
     InsertingWebsite,
+    InsertingWindow,
     InsertingHour,
     InsertingMinute,
 }
@@ -57,19 +61,30 @@ pub struct App<'a> {
     pub input_name: String,
     pub input_email: String,
     pub input_website: String,
+    pub input_window: String,
     pub cursor_position_name: usize,
     pub cursor_position_email: usize,
     pub cursor_position_website: usize,
+    pub cursor_position_window: usize,
     pub input_mode: InputMode,
     pub name: String,
     pub email: String,
     pub website: String,
     pub hr: String,
     pub min: String,
+    // the window typed for the next target and why it was not taken
+    pub window: Period,
+    pub window_error: String,
+    pub targets: Vec<Target>,
+    pub settings: Settings,
+    pub monitor: Option<Monitor>,
+    // the target picked in the status while checking
+    pub selected: usize,
+    // how far back the statistics in the status go
+    pub stats_range: String,
     pub hr_items: StatefulList<&'a str>,
     pub min_items: StatefulList<&'a str>,
     pub ending_connected: bool,
-    pub ending_disconnected: bool,
 }
 
 impl<'a> App<'a> {
@@ -80,20 +95,29 @@ impl<'a> App<'a> {
             input_name: String::new(),
             input_email: String::new(),
             input_website: String::new(),
+            input_window: String::new(),
             cursor_position_name: 0,
             cursor_position_email: 0,
             cursor_position_website: 0,
+            cursor_position_window: 0,
             input_mode: InputMode::Normal,
             name: String::new(),
             email: String::new(),
             website: String::new(),
             hr: String::new(),
             min: String::new(),
+            window: Period::default(),
+            window_error: String::new(),
+            targets: Vec::new(),
+            settings: Settings::default(),
+            monitor: None,
+            selected: 0,
+            stats_range: String::new(),
             hr_items: StatefulList::with_items(vec![
-                "01","02","03","04","05","06",
-                "07","08","09","10","11","12",
-                "13","14","15","16","17","18",
-                "19","20","21","22","23","24",
+                "00","01","02","03","04","05",
+                "06","07","08","09","10","11",
+                "12","13","14","15","16","17",
+                "18","19","20","21","22","23",
             ]),
             min_items: StatefulList::with_items(vec![
                 "00","01","02","03","04","05","06","07","08","09",
@@ -103,8 +127,7 @@ impl<'a> App<'a> {
                 "40","41","42","43","44","45","46","47","48","49",
                 "50","51","52","53","54","55","56","57","58","59",
             ]),
-            ending_connected: false,
-            ending_disconnected: false
+            ending_connected: false
         }
     }
 
@@ -120,6 +143,15 @@ impl<'a> App<'a> {
         self.index = 0;
     }
 
+    // Goes on to the next range of the statistics, a range from the config that is not in the list starts the list over
+    pub fn next_stats_range(&mut self) {
+        let next = match STATS_RANGES.iter().position(|range| *range == self.stats_range) {
+            Some(index) => (index + 1) % STATS_RANGES.len(),
+            None => 0,
+        };
+        self.stats_range = STATS_RANGES[next].to_string();
+    }
+
     pub fn move_cursor_left_name(&mut self) {
         let cursor_moved_left = self.cursor_position_name.saturating_sub(1);
         self.cursor_position_name = self.clamp_cursor_name(cursor_moved_left);
@@ -150,6 +182,16 @@ impl<'a> App<'a> {
         self.cursor_position_website = self.clamp_cursor_website(cursor_moved_right);
     }
 
+    pub fn move_cursor_left_window(&mut self) {
+        let cursor_moved_left = self.cursor_position_window.saturating_sub(1);
+        self.cursor_position_window = self.clamp_cursor_window(cursor_moved_left);
+    }
+
+    pub fn move_cursor_right_window(&mut self) {
+        let cursor_moved_right = self.cursor_position_window.saturating_add(1);
+        self.cursor_position_window = self.clamp_cursor_window(cursor_moved_right);
+    }
+
     pub fn enter_char_name(&mut self, new_char: char) {
         self.input_name.insert(self.cursor_position_name, new_char);
 
@@ -168,6 +210,12 @@ impl<'a> App<'a> {
         self.move_cursor_right_website();
     }
 
+    pub fn enter_char_window(&mut self, new_char: char) {
+        self.input_window.insert(self.cursor_position_window, new_char);
+
+        self.move_cursor_right_window();
+    }
+
     pub fn delete_char_name(&mut self) {
         let is_not_cursor_leftmost = self.cursor_position_name != 0;
         if is_not_cursor_leftmost {
@@ -234,6 +282,20 @@ impl<'a> App<'a> {
         }
     }
 
+    pub fn delete_char_window(&mut self) {
+        let is_not_cursor_leftmost = self.cursor_position_window != 0;
+        if is_not_cursor_leftmost {
+            let current_index = self.cursor_position_window;
+            let from_left_to_current_index = current_index - 1;
+
+            let before_char_to_delete = self.input_window.chars().take(from_left_to_current_index);
+            let after_char_to_delete = self.input_window.chars().skip(current_index);
+
+            self.input_window = before_char_to_delete.chain(after_char_to_delete).collect();
+            self.move_cursor_left_window();
+        }
+    }
+
     pub fn clamp_cursor_name(&self, new_cursor_pos: usize) -> usize {
         new_cursor_pos.clamp(0, self.input_name.len())
     }
@@ -246,6 +308,10 @@ impl<'a> App<'a> {
         new_cursor_pos.clamp(0, self.input_website.len())
     }
 
+    pub fn clamp_cursor_window(&self, new_cursor_pos: usize) -> usize {
+        new_cursor_pos.clamp(0, self.input_window.len())
+    }
+
     pub fn reset_cursor_name(&mut self) {
         self.cursor_position_name = 0;
     }
@@ -258,6 +324,10 @@ impl<'a> App<'a> {
         self.cursor_position_website = 0;
     }
 
+    pub fn reset_cursor_window(&mut self) {
+        self.cursor_position_window = 0;
+    }
+
     pub fn submit_name(&mut self) {
         self.name.push_str(&self.input_name.clone());
         self.input_name.clear();
@@ -275,4 +345,43 @@ impl<'a> App<'a> {
         self.input_website.clear();
         self.reset_cursor_website();
     }
-}
\ No newline at end of file
+
+    // Keeps the typed window for the next target when it makes sense, gives back false and keeps the reason when it doesn't
+    pub fn submit_window(&mut self) -> bool {
+        let daemon = self.settings.daemon;
+        let checked = parse_window(&self.input_window)
+            .and_then(|period| check_run_period(&period, self.settings.zone).map(|_| period))
+            .and_then(|period| {
+                // The same rules as a schedule in the config, without the daemon the target has to stop at some point
+                if !daemon && period.end.is_none() && period.duration.is_none() {
+                    return Err("needs an end when daemon is off, add until or for".to_string());
+                }
+                if !daemon && period.repeat != Repeat::Once {
+                    return Err("a repeating window needs daemon mode".to_string());
+                }
+                Ok(period)
+            });
+        match checked {
+            Ok(period) => {
+                self.window = period;
+                self.window_error.clear();
+                self.input_window.clear();
+                self.reset_cursor_window();
+                true
+            }
+            Err(err) => {
+                self.window_error = err;
+                false
+            }
+        }
+    }
+
+    pub fn submit_target(&mut self) {
+        let mut target = Target::from_input(&self.website, &self.hr, &self.min);
+        target.schedule.window = std::mem::take(&mut self.window);
+        self.targets.push(target);
+        self.website.clear();
+        self.hr.clear();
+        self.min.clear();
+    }
+}
@@ -0,0 +1,273 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, Utc, Weekday};
+use crate::config::{parse_duration, Period, Repeat, Schedule, Zone};
+
+// A point in a period, either a full date and time or only a time of the day
+#[derive(Clone, Copy)]
+pub enum Moment {
+    At(NaiveDateTime),
+    Daily(NaiveTime),
+}
+
+// A function that will read "2024-01-31 22:00" or "22:00"
+pub fn parse_moment(text: &str) -> Option<Moment> {
+    if let Ok(at) = NaiveDateTime::parse_from_str(text.trim(), "%Y-%m-%d %H:%M") {
+        return Some(Moment::At(at));
+    }
+    NaiveTime::parse_from_str(text.trim(), "%H:%M").ok().map(Moment::Daily)
+}
+
+// A function that will read a window typed in the TUI e.g. "from 2024-01-31 22:00 until 02:00" or "from 09:00 for 8h weekly mon,fri"
+pub fn parse_window(text: &str) -> Result<Period, String> {
+    let mut period = Period::default();
+    let mut words = text.split_whitespace();
+
+    while let Some(word) = words.next() {
+        match word {
+            "from" | "until" => {
+                let mut moment = words.next().ok_or(format!("{} needs a time", word))?.to_string();
+                // A date is followed by its time
+                if !moment.contains(':') {
+                    let time = words.next().ok_or(format!("{} {} needs a time", word, moment))?;
+                    moment = format!("{} {}", moment, time);
+                }
+                if parse_moment(&moment).is_none() {
+                    return Err(format!("invalid time \"{}\", use YYYY-MM-DD HH:MM or HH:MM", moment));
+                }
+                if word == "from" {
+                    period.start = Some(moment);
+                }
+                else {
+                    period.end = Some(moment);
+                }
+            }
+            "for" => {
+                let duration = words.next().ok_or("for needs a duration e.g. 3h".to_string())?;
+                period.duration = Some(duration.to_string());
+            }
+            "daily" => period.repeat = Repeat::Daily,
+            "weekly" => {
+                period.repeat = Repeat::Weekly;
+                let days = words.next().ok_or("weekly needs days e.g. mon,thu".to_string())?;
+                period.days = days.split(',').map(|day| day.trim().to_string()).collect();
+            }
+            _ => return Err(format!("unknown word \"{}\", use from, until, for, daily or weekly", word)),
+        }
+    }
+
+    Ok(period)
+}
+
+// One stretch of time that a period is open, no end means until the app is stopped
+pub struct Window {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+fn weekdays(period: &Period) -> Vec<Weekday> {
+    period.days.iter().filter_map(|day| day.parse::<Weekday>().ok()).collect()
+}
+
+// A function that will find where a window that opens at `start` closes, an end time before the start is on the next day
+fn window_from(period: &Period, zone: Zone, start: DateTime<Utc>) -> Window {
+    let end = match (&period.end, &period.duration) {
+        (Some(end), _) => match parse_moment(end).unwrap() {
+            Moment::At(at) => zone.at(at.date(), at.time()),
+            Moment::Daily(time) => Some(zone.next_at(start, time)),
+        },
+        (None, Some(duration)) => Some(start + chrono::Duration::from_std(parse_duration(duration).unwrap()).unwrap()),
+        (None, None) => None,
+    };
+
+    Window { start, end }
+}
+
+// A function that will find the window that is open now or the next one to open, None when every window is over
+pub fn next_window(period: &Period, zone: Zone, now: DateTime<Utc>) -> Option<Window> {
+    let start = match &period.start {
+        Some(start) => parse_moment(start).unwrap(),
+        None => return Some(window_from(period, zone, now)).filter(|window| window.end.is_none_or(|end| end > now)),
+    };
+
+    /* Start looking a day back so a window that opened yesterday and crosses midnight is still found,
+    a window without an end never closes so it would always be found there, it starts looking today */
+    let today = zone.date_of(now);
+    let first = if period.end.is_none() && period.duration.is_none() { today } else { today.pred_opt().unwrap() };
+    let (mut date, time, tries) = match (start, period.repeat) {
+        (Moment::At(at), Repeat::Once) => (at.date(), at.time(), 1),
+        (Moment::Daily(time), Repeat::Once) => (first, time, 3),
+        (Moment::At(at), _) => (at.date().max(first), at.time(), 9),
+        (Moment::Daily(time), _) => (first, time, 9),
+    };
+
+    let days = weekdays(period);
+    for _ in 0..tries {
+        let on_day = period.repeat != Repeat::Weekly || days.contains(&date.weekday());
+        if let (true, Some(start)) = (on_day, zone.at(date, time)) {
+            let window = window_from(period, zone, start);
+            if window.end.is_none_or(|end| end > now) {
+                return Some(window);
+            }
+        }
+        date = date.succ_opt().unwrap();
+    }
+
+    None
+}
+
+// Whether a period is open at a moment
+pub fn is_open(period: &Period, zone: Zone, now: DateTime<Utc>) -> bool {
+    next_window(period, zone, now).is_some_and(|window| window.start <= now)
+}
+
+// A function that will check that a period makes sense
+pub fn check_period(period: &Period, zone: Zone) -> Result<(), String> {
+    let start = match &period.start {
+        Some(start) => Some(parse_moment(start).ok_or(format!("invalid start \"{}\"", start))?),
+        None => None,
+    };
+    let end = match &period.end {
+        Some(end) => Some(parse_moment(end).ok_or(format!("invalid end \"{}\"", end))?),
+        None => None,
+    };
+    if let Some(duration) = &period.duration {
+        if parse_duration(duration).is_none() {
+            return Err(format!("invalid duration \"{}\"", duration));
+        }
+    }
+    if end.is_some() && period.duration.is_some() {
+        return Err("use either end or duration, not both".to_string());
+    }
+
+    for moment in [start, end].into_iter().flatten() {
+        if let Moment::At(at) = moment {
+            if zone.at(at.date(), at.time()).is_none() {
+                return Err(format!("{} does not exist in {}", at.format("%Y-%m-%d %H:%M"), zone.name()));
+            }
+        }
+    }
+    if let (Some(Moment::At(start)), Some(Moment::At(end))) = (start, end) {
+        if end <= start {
+            return Err("end must be after start".to_string());
+        }
+    }
+
+    if period.repeat != Repeat::Once {
+        if start.is_none() {
+            return Err("a repeating window needs a start".to_string());
+        }
+        if end.is_none() && period.duration.is_none() {
+            return Err("a repeating window needs an end or a duration".to_string());
+        }
+        if let Some(Moment::At(_)) = end {
+            return Err("a repeating window needs an end time without a date".to_string());
+        }
+    }
+    if period.repeat == Repeat::Weekly {
+        if period.days.is_empty() {
+            return Err("a weekly window needs days".to_string());
+        }
+        if let Some(day) = period.days.iter().find(|day| day.parse::<Weekday>().is_err()) {
+            return Err(format!("invalid day \"{}\"", day));
+        }
+    }
+    else if !period.days.is_empty() {
+        return Err("days only work with a weekly repeat".to_string());
+    }
+
+    Ok(())
+}
+
+// Whether a one-off period is over and will never open again
+pub fn is_over(period: &Period, zone: Zone, now: DateTime<Utc>) -> bool {
+    period.repeat == Repeat::Once && next_window(period, zone, now).is_none()
+}
+
+// A function that will check a period that a target is checked in, unlike a maintenance window it is no use once it is over
+pub fn check_run_period(period: &Period, zone: Zone) -> Result<(), String> {
+    check_period(period, zone)?;
+    if is_over(period, zone, Utc::now()) {
+        return Err("the window is already over".to_string());
+    }
+    Ok(())
+}
+
+// A function that will check that the window of a schedule makes sense
+pub fn check_window(schedule: &Schedule, zone: Zone) -> Result<(), String> {
+    if schedule.hour.is_empty() != schedule.minute.is_empty() {
+        return Err("hour and minute must be set together".to_string());
+    }
+    if !schedule.hour.is_empty() {
+        if schedule.window.end.is_some() || schedule.window.duration.is_some() {
+            return Err("use only one of end, duration or hour and minute".to_string());
+        }
+        if NaiveTime::parse_from_str(&format!("{}:{}", schedule.hour, schedule.minute), "%H:%M").is_err() {
+            return Err(format!("invalid end time {}:{}", schedule.hour, schedule.minute));
+        }
+    }
+
+    check_run_period(&schedule.period(), zone)
+}
+
+// A function that will describe a period e.g. "From 22:00 Until 02:00 daily"
+pub fn describe_period(period: &Period) -> String {
+    let mut text = String::new();
+    if let Some(start) = &period.start {
+        text.push_str(format!("From {} ", start).as_str());
+    }
+
+    if let Some(end) = &period.end {
+        text.push_str(format!("Until {}", end).as_str());
+    }
+    else if let Some(duration) = &period.duration {
+        text.push_str(format!("For {}", duration).as_str());
+    }
+    else {
+        text.push_str("Until stopped");
+    }
+
+    match period.repeat {
+        Repeat::Once => {}
+        Repeat::Daily => text.push_str(" daily"),
+        Repeat::Weekly => text.push_str(format!(" every {}", period.days.join(", ")).as_str()),
+    }
+    text
+}
+
+// A function that will describe the window of a schedule for the Time panel
+pub fn describe_window(schedule: &Schedule) -> String {
+    if !schedule.hour.is_empty() && schedule.window.start.is_none() {
+        return format!("Notify When {} : {}", schedule.hour, schedule.minute);
+    }
+    describe_period(&schedule.period())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn utc(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 31, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn a_start_without_an_end_waits_for_today() {
+        let zone = Zone::Named(chrono_tz::UTC);
+        let period = parse_window("from 22:00").unwrap();
+        let window = next_window(&period, zone, utc(13, 23)).unwrap();
+        assert_eq!(window.start, utc(22, 0));
+        assert!(window.end.is_none());
+        assert!(!is_open(&period, zone, utc(13, 23)));
+        assert!(is_open(&period, zone, utc(22, 30)));
+    }
+
+    #[test]
+    fn a_window_from_yesterday_is_still_open_after_midnight() {
+        let zone = Zone::Named(chrono_tz::UTC);
+        let period = parse_window("from 22:00 until 02:00").unwrap();
+        let window = next_window(&period, zone, utc(1, 0)).unwrap();
+        assert_eq!(window.start, Utc.with_ymd_and_hms(2024, 1, 30, 22, 0, 0).unwrap());
+        assert_eq!(window.end, Some(utc(2, 0)));
+        assert!(!is_open(&period, zone, utc(13, 23)));
+    }
+}